@@ -0,0 +1,212 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::io::Cursor;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use quinn::{Endpoint, NewConnection};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+use crate::http::{Client, DnsRequest};
+use crate::{DnsPacket, FromBytes};
+
+// an open connection to a DoQ server, its endpoint is kept to wait for a clean close
+pub(crate) struct QuicConnection {
+    endpoint: Endpoint,
+    connection: quinn::Connection,
+}
+
+// one per server, it stays locked while a connection to that server is set up
+pub(crate) type QuicSlot = Arc<Mutex<Option<QuicConnection>>>;
+
+impl Client {
+    // DNS over QUIC (RFC 9250). The connection to a server is kept open, every query gets its own
+    // stream on it and is framed like DoT
+    pub async fn send_doq(
+        &self,
+        request: DnsRequest,
+    ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let addr = self.lookup(&request.host, 853)?;
+        let host_name = match &request.host_name {
+            Some(host_name) => host_name.clone(),
+            None => addr.ip().to_string(),
+        };
+        // the stream already identifies the query, so the id has to be 0
+        let mut packet = request.body.clone();
+        packet.header.transaction_id = 0;
+
+        let slot = self
+            .quic_connections
+            .lock()
+            .unwrap()
+            .entry(format!("{}/{}", addr, host_name))
+            .or_default()
+            .clone();
+        let mut response = timeout(self.timeout, async {
+            let (connection, reused) = self.quic_connection(&slot, addr, &host_name, None).await?;
+            match exchange_doq(&connection, &packet).await {
+                // the server might have closed the idle connection, so try again on a new one
+                Err(_) if reused => {
+                    let stale = Some(connection.stable_id());
+                    let (connection, _) =
+                        self.quic_connection(&slot, addr, &host_name, stale).await?;
+                    exchange_doq(&connection, &packet).await
+                }
+                result => result,
+            }
+        })
+        .await
+        .map_err(|_| format!("no response from {} within {:?}", addr, self.timeout))??;
+        response.header.transaction_id = request.body.get_transaction_id();
+        Ok(response)
+    }
+
+    // closes the open DoQ connections and waits until the servers were told about it
+    pub async fn close_doq(&self) {
+        let slots: Vec<QuicSlot> = self
+            .quic_connections
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, slot)| slot)
+            .collect();
+        for slot in slots {
+            if let Some(quic) = slot.lock().await.take() {
+                quic.connection.close(0u32.into(), b"");
+                quic.endpoint.wait_idle().await;
+            }
+        }
+    }
+
+    // the open connection of the slot, unless it is the `stale` one. The flag tells whether the
+    // connection was opened before
+    async fn quic_connection(
+        &self,
+        slot: &QuicSlot,
+        addr: SocketAddr,
+        host_name: &str,
+        stale: Option<usize>,
+    ) -> Result<(quinn::Connection, bool), Box<dyn std::error::Error>> {
+        let mut current = slot.lock().await;
+        if let Some(quic) = current.as_ref() {
+            if Some(quic.connection.stable_id()) != stale {
+                return Ok((quic.connection.clone(), true));
+            }
+        }
+        if let Some(quic) = current.take() {
+            quic.connection.close(0u32.into(), b"");
+        }
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let mut endpoint = Endpoint::client(local)?;
+        endpoint.set_default_client_config(quinn::ClientConfig::new(self.quic_config.clone()));
+        let NewConnection { connection, .. } = endpoint.connect(addr, host_name)?.await?;
+        *current = Some(QuicConnection {
+            endpoint,
+            connection: connection.clone(),
+        });
+        Ok((connection, false))
+    }
+}
+
+async fn exchange_doq(
+    connection: &quinn::Connection,
+    packet: &DnsPacket,
+) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+    let bytes = packet.to_vec()?;
+    let mut frame = (bytes.len() as u16).to_be_bytes().to_vec();
+    frame.extend(bytes);
+
+    let (mut send, receive) = connection.open_bi().await?;
+    send.write_all(&frame).await?;
+    send.finish().await?;
+    let response = receive.read_to_end(2 + u16::MAX as usize).await?;
+
+    if response.len() < 2
+        || response.len() - 2 != u16::from_be_bytes([response[0], response[1]]) as usize
+    {
+        return Err("DoQ response has the wrong length".into());
+    }
+    let response = DnsPacket::read(&mut Cursor::new(&response[2..]))?;
+    if response.get_transaction_id() != 0 {
+        return Err("DoQ response has a transaction id other than 0".into());
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use tokio::runtime::Builder;
+
+    use crate::test_util::{a_response, self_signed_server, trusting_client};
+    use crate::{RData, RecordType};
+
+    use super::*;
+
+    #[test]
+    fn test_doq() {
+        let (certificate, server_crypto) = self_signed_server("localhost", &[b"doq"]);
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
+        let mut client = trusting_client(&certificate);
+        client.set_timeout(Duration::from_secs(1));
+
+        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let (endpoint, mut incoming) =
+                Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+            let server_addr = endpoint.local_addr().unwrap();
+            // only the first connection is accepted, later queries have to reuse it
+            tokio::spawn(async move {
+                let NewConnection { mut bi_streams, .. } =
+                    incoming.next().await.unwrap().await.unwrap();
+                while let Some(Ok((mut send, receive))) = bi_streams.next().await {
+                    let query = receive.read_to_end(u16::MAX as usize).await.unwrap();
+                    let query = DnsPacket::read(&mut Cursor::new(&query[2..])).unwrap();
+                    assert_eq!(query.get_transaction_id(), 0);
+                    let response = a_response(&query, 1).to_vec().unwrap();
+                    send.write_all(&(response.len() as u16).to_be_bytes())
+                        .await
+                        .unwrap();
+                    send.write_all(&response).await.unwrap();
+                    send.finish().await.unwrap();
+                }
+            });
+
+            let request = |server_addr: SocketAddr| {
+                let query = DnsPacket::builder()
+                    .add_query("www.example.com", RecordType::A)
+                    .unwrap()
+                    .build();
+                DnsRequest::new_with_host("localhost", server_addr.to_string(), query)
+            };
+            for _ in 0..2 {
+                let request = request(server_addr);
+                let id = request.body.get_transaction_id();
+                let response = client.send_doq(request).await.unwrap();
+                assert_eq!(response.get_transaction_id(), id);
+                assert_eq!(
+                    response.answers[0].get_rdata().unwrap(),
+                    RData::A(Ipv4Addr::new(192, 0, 2, 1))
+                );
+            }
+            client.close_doq().await;
+            assert!(client.quic_connections.lock().unwrap().is_empty());
+
+            // a server that never answers is given up on after the timeout
+            let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            let silent_addr = silent.local_addr().unwrap();
+            let started = std::time::Instant::now();
+            assert!(client.send_doq(request(silent_addr)).await.is_err());
+            assert!(started.elapsed() < Duration::from_secs(2));
+        });
+    }
+}
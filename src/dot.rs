@@ -0,0 +1,417 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{oneshot, Mutex};
+
+use crate::http::Client;
+use crate::{Answer, DnsPacket, FromBytes};
+
+// EDNS option code of the TCP keepalive option (RFC 7828)
+const TCP_KEEPALIVE: u16 = 11;
+
+// a DNS over TLS session that is kept open between queries. Queries can be sent concurrently
+// and are pipelined on one stream, responses are matched by their transaction id
+pub struct DotSession {
+    client: Client,
+    host_name: String,
+    server_addr: String,
+    connection: Mutex<Option<Arc<Connection>>>,
+}
+
+impl Client {
+    pub fn dot_session(&self, host_name: &str, server_addr: &str) -> DotSession {
+        DotSession {
+            client: self.clone(),
+            host_name: host_name.to_string(),
+            server_addr: server_addr.to_string(),
+            connection: Mutex::new(None),
+        }
+    }
+}
+
+impl DotSession {
+    pub async fn query(&self, packet: DnsPacket) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let mut packet = packet;
+        packet.add_tcp_keepalive();
+        let connection = self.connection().await?;
+        match connection.exchange(&packet, self.client.timeout).await {
+            // the server closed the idle connection before it got our query, so it is sent again
+            // on a new one
+            Err(_) if connection.is_closed() => {
+                let connection = self.connection().await?;
+                connection.exchange(&packet, self.client.timeout).await
+            }
+            result => result,
+        }
+    }
+
+    // closes the connection, the next query opens a new one
+    pub async fn close(&self) {
+        if let Some(connection) = self.connection.lock().await.take() {
+            connection.shutdown().await;
+        }
+    }
+
+    async fn connection(&self) -> Result<Arc<Connection>, Box<dyn std::error::Error>> {
+        let mut current = self.connection.lock().await;
+        if let Some(connection) = current.as_ref() {
+            if connection.is_usable() {
+                return Ok(connection.clone());
+            }
+            connection.shutdown().await;
+        }
+        let stream = self
+            .client
+            .connect_dot(self.server_addr.clone(), self.host_name.clone())
+            .await?;
+        let connection = Arc::new(Connection::start(stream));
+        *current = Some(connection.clone());
+        Ok(connection)
+    }
+}
+
+struct ConnectionState {
+    pending: HashMap<u16, oneshot::Sender<DnsPacket>>,
+    last_activity: Instant,
+    // the idle timeout the server announced with the keepalive option
+    keepalive: Option<Duration>,
+    closed: bool,
+}
+
+struct Connection {
+    writer: Mutex<Box<dyn AsyncWrite + Send + Unpin>>,
+    state: Arc<StdMutex<ConnectionState>>,
+}
+
+impl Connection {
+    fn start<S>(stream: S) -> Connection
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let state = Arc::new(StdMutex::new(ConnectionState {
+            pending: HashMap::new(),
+            last_activity: Instant::now(),
+            keepalive: None,
+            closed: false,
+        }));
+        tokio::spawn(read_responses(reader, state.clone()));
+        Connection {
+            writer: Mutex::new(Box::new(writer)),
+            state,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    // a connection without outstanding queries is given up once the server's keepalive
+    // timeout has passed, since the server is going to close it anyway
+    fn is_usable(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        !state.closed
+            && (!state.pending.is_empty()
+                || state
+                    .keepalive
+                    .map(|keepalive| state.last_activity.elapsed() < keepalive)
+                    .unwrap_or(true))
+    }
+
+    async fn shutdown(&self) {
+        let _ = self.writer.lock().await.shutdown().await;
+        self.state.lock().unwrap().closed = true;
+    }
+
+    async fn exchange(
+        &self,
+        packet: &DnsPacket,
+        timeout: Duration,
+    ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let transaction_id = packet.get_transaction_id();
+        let mut packet = packet.clone();
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Err("connection is closed".into());
+            }
+            // concurrent queries need distinct ids on the same stream
+            while state.pending.contains_key(&packet.header.transaction_id) {
+                packet.header.transaction_id = rand::random();
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.pending.insert(packet.header.transaction_id, sender);
+            state.last_activity = Instant::now();
+            receiver
+        };
+        let id = packet.header.transaction_id;
+
+        let bytes = packet.to_vec()?;
+        let mut frame = (bytes.len() as u16).to_be_bytes().to_vec();
+        frame.extend(bytes);
+        if let Err(error) = self.writer.lock().await.write_all(&frame).await {
+            let mut state = self.state.lock().unwrap();
+            state.pending.remove(&id);
+            state.closed = true;
+            return Err(error.into());
+        }
+
+        let mut response = match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => return Err("connection was closed before the response arrived".into()),
+            Err(_) => {
+                self.state.lock().unwrap().pending.remove(&id);
+                return Err(format!("no response within {:?}", timeout).into());
+            }
+        };
+        response.header.transaction_id = transaction_id;
+        Ok(response)
+    }
+}
+
+async fn read_responses<R>(mut reader: R, state: Arc<StdMutex<ConnectionState>>)
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        let mut length = [0; 2];
+        if reader.read_exact(&mut length).await.is_err() {
+            break;
+        }
+        let mut buffer = vec![0; u16::from_be_bytes(length) as usize];
+        if reader.read_exact(&mut buffer).await.is_err() {
+            break;
+        }
+        let response = match DnsPacket::read(&mut Cursor::new(&buffer)) {
+            Ok(response) => response,
+            Err(_) => continue,
+        };
+        let mut state = state.lock().unwrap();
+        state.last_activity = Instant::now();
+        if let Some(keepalive) = response.tcp_keepalive() {
+            state.keepalive = Some(keepalive);
+        }
+        if let Some(sender) = state.pending.remove(&response.get_transaction_id()) {
+            let _ = sender.send(response);
+        }
+    }
+    // dropping the senders wakes up everyone still waiting
+    let mut state = state.lock().unwrap();
+    state.closed = true;
+    state.pending.clear();
+}
+
+impl DnsPacket {
+    // asks the server to keep the connection open (RFC 7828), only meant for TCP and TLS
+    pub fn add_tcp_keepalive(&mut self) {
+        if !self.additional_options.iter().any(|answer| answer.ty == 41) {
+            self.additional_options.push(Answer::new_opt());
+            self.update_counts();
+        }
+        let opt = self
+            .additional_options
+            .iter_mut()
+            .find(|answer| answer.ty == 41)
+            .unwrap();
+        if edns_option(&opt.data, TCP_KEEPALIVE).is_none() {
+            opt.data.extend(TCP_KEEPALIVE.to_be_bytes());
+            opt.data.extend(0u16.to_be_bytes());
+            opt.rd_length = opt.data.len() as u16;
+        }
+    }
+
+    // the idle timeout a server announced with the keepalive option
+    pub fn tcp_keepalive(&self) -> Option<Duration> {
+        let opt = self
+            .additional_options
+            .iter()
+            .find(|answer| answer.ty == 41)?;
+        match edns_option(&opt.data, TCP_KEEPALIVE)? {
+            [high, low] => Some(Duration::from_millis(
+                u16::from_be_bytes([*high, *low]) as u64 * 100,
+            )),
+            _ => None,
+        }
+    }
+}
+
+// the data of an option in OPT rdata, which is a list of code, length and data
+fn edns_option(data: &[u8], code: u16) -> Option<&[u8]> {
+    let mut rest = data;
+    while rest.len() >= 4 {
+        let option_code = u16::from_be_bytes([rest[0], rest[1]]);
+        let length = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        let option = rest.get(4..4 + length)?;
+        if option_code == code {
+            return Some(option);
+        }
+        rest = &rest[4 + length..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use tokio::net::TcpListener;
+    use tokio::runtime::Builder;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::test_util::{a_response, self_signed_server, trusting_client};
+    use crate::{RData, RecordType};
+
+    use super::*;
+
+    async fn read_query<S: AsyncRead + Unpin>(stream: &mut S) -> DnsPacket {
+        let mut length = [0; 2];
+        stream.read_exact(&mut length).await.unwrap();
+        let mut buffer = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut buffer).await.unwrap();
+        DnsPacket::read(&mut Cursor::new(&buffer)).unwrap()
+    }
+
+    async fn write_response<S: AsyncWrite + Unpin>(
+        stream: &mut S,
+        query: &DnsPacket,
+        last_octet: u8,
+    ) {
+        let mut response = a_response(query, last_octet);
+        response.add_tcp_keepalive();
+        // a keepalive timeout of 5 seconds
+        let opt = response.additional_options.last_mut().unwrap();
+        opt.data = vec![0, 11, 0, 2, 0, 50];
+        opt.rd_length = 6;
+        let bytes = response.to_vec().unwrap();
+        stream
+            .write_all(&(bytes.len() as u16).to_be_bytes())
+            .await
+            .unwrap();
+        stream.write_all(&bytes).await.unwrap();
+    }
+
+    fn query(name: &str, transaction_id: u16) -> DnsPacket {
+        let mut packet = DnsPacket::builder()
+            .add_query(name, RecordType::A)
+            .unwrap()
+            .build();
+        packet.header.transaction_id = transaction_id;
+        packet.add_tcp_keepalive();
+        packet
+    }
+
+    #[test]
+    fn test_pipelining() {
+        let rt = Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let (client, mut server) = tokio::io::duplex(4096);
+            let connection = Connection::start(client);
+            let server = tokio::spawn(async move {
+                let first = read_query(&mut server).await;
+                let second = read_query(&mut server).await;
+                assert_ne!(first.get_transaction_id(), second.get_transaction_id());
+                // clients send the keepalive option without a timeout
+                let opt = first
+                    .additional_options
+                    .iter()
+                    .find(|a| a.ty == 41)
+                    .unwrap();
+                assert_eq!(edns_option(&opt.data, TCP_KEEPALIVE), Some(&[][..]));
+                assert_eq!(first.tcp_keepalive(), None);
+                // answered out of order
+                write_response(&mut server, &second, 2).await;
+                write_response(&mut server, &first, 1).await;
+                server
+            });
+
+            let timeout = Duration::from_secs(5);
+            let first = query("one.example", 7);
+            let second = query("two.example", 7);
+            let (first, second) = tokio::join!(
+                connection.exchange(&first, timeout),
+                connection.exchange(&second, timeout)
+            );
+            let (first, second) = (first.unwrap(), second.unwrap());
+            assert_eq!(first.get_transaction_id(), 7);
+            assert_eq!(second.get_transaction_id(), 7);
+            assert_eq!(first.queries[0].name.to_string(), "one.example.");
+            assert_eq!(
+                second.answers[0].get_rdata().unwrap(),
+                RData::A(Ipv4Addr::new(192, 0, 2, 2))
+            );
+            assert_eq!(first.tcp_keepalive(), Some(Duration::from_secs(5)));
+            assert!(connection.is_usable());
+
+            // the server going away is noticed
+            drop(server.await.unwrap());
+            let result = connection
+                .exchange(&query("three.example", 8), timeout)
+                .await;
+            assert!(result.is_err());
+            assert!(connection.is_closed());
+            assert!(!connection.is_usable());
+        });
+    }
+
+    #[test]
+    fn test_session_reconnects() {
+        let (certificate, server_config) = self_signed_server("dns.example", &[]);
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            // every connection answers a single query and is then closed by the server
+            let server = tokio::spawn(async move {
+                for last_octet in 1..=2 {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let mut stream = acceptor.accept(stream).await.unwrap();
+                    let query = read_query(&mut stream).await;
+                    write_response(&mut stream, &query, last_octet).await;
+                    stream.shutdown().await.unwrap();
+                }
+            });
+
+            let client = trusting_client(&certificate);
+            let session = client.dot_session("dns.example", &format!("127.0.0.1:{}", port));
+            let first = session.query(query("one.example", 1)).await.unwrap();
+            assert_eq!(
+                first.answers[0].get_rdata().unwrap(),
+                RData::A(Ipv4Addr::new(192, 0, 2, 1))
+            );
+            let second = session.query(query("two.example", 2)).await.unwrap();
+            assert_eq!(
+                second.answers[0].get_rdata().unwrap(),
+                RData::A(Ipv4Addr::new(192, 0, 2, 2))
+            );
+            server.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_keepalive_option() {
+        let mut packet = query("www.example.com", 1);
+        packet.add_tcp_keepalive();
+        let opt = packet
+            .additional_options
+            .iter()
+            .find(|a| a.ty == 41)
+            .unwrap();
+        assert_eq!(opt.data, vec![0, 11, 0, 0]);
+        assert_eq!(packet.header.number_of_additional, 1);
+        assert_eq!(
+            edns_option(&[0, 10, 0, 1, 9, 0, 11, 0, 2, 1, 0], 11),
+            Some(&[1, 0][..])
+        );
+        assert_eq!(edns_option(&[0, 11, 0, 4, 1], 11), None);
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use ring::digest::{digest, Algorithm, SHA1_FOR_LEGACY_USE_ONLY, SHA256, SHA512};
+
+use crate::RData;
+
+impl RData {
+    // checks a DER encoded certificate against a TLSA record, depending on the selector either the
+    // whole certificate or its SubjectPublicKeyInfo is compared
+    pub fn matches_certificate(
+        &self,
+        certificate: &[u8],
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match self {
+            RData::TLSA { selector: 0, .. } => self.matches_tlsa(certificate),
+            RData::TLSA { selector: 1, .. } => {
+                self.matches_tlsa(spki_from_certificate(certificate)?)
+            }
+            RData::TLSA { selector, .. } => {
+                Err(format!("unknown TLSA selector {}", selector).into())
+            }
+            _ => Err("not a TLSA record".into()),
+        }
+    }
+
+    pub fn matches_spki(&self, spki: &[u8]) -> Result<bool, Box<dyn std::error::Error>> {
+        match self {
+            RData::TLSA { selector: 1, .. } => self.matches_tlsa(spki),
+            RData::TLSA { .. } => Err("TLSA record does not select the public key".into()),
+            _ => Err("not a TLSA record".into()),
+        }
+    }
+
+    // `host_key` is the key in SSH wire format, as it is base64 encoded in known_hosts files
+    pub fn matches_ssh_host_key(
+        &self,
+        host_key: &[u8],
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match self {
+            RData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => {
+                if ssh_key_algorithm(host_key)? != *algorithm {
+                    return Ok(false);
+                }
+                let hash = match fingerprint_type {
+                    1 => &SHA1_FOR_LEGACY_USE_ONLY,
+                    2 => &SHA256,
+                    other => return Err(format!("unknown SSHFP fingerprint type {}", other).into()),
+                };
+                Ok(digest(hash, host_key).as_ref() == fingerprint.as_slice())
+            }
+            _ => Err("not an SSHFP record".into()),
+        }
+    }
+
+    fn matches_tlsa(&self, selected: &[u8]) -> Result<bool, Box<dyn std::error::Error>> {
+        let (matching_type, data) = match self {
+            RData::TLSA {
+                matching_type,
+                data,
+                ..
+            } => (*matching_type, data),
+            _ => return Err("not a TLSA record".into()),
+        };
+        let hash: &Algorithm = match matching_type {
+            0 => return Ok(selected == data.as_slice()),
+            1 => &SHA256,
+            2 => &SHA512,
+            other => return Err(format!("unknown TLSA matching type {}", other).into()),
+        };
+        Ok(digest(hash, selected).as_ref() == data.as_slice())
+    }
+}
+
+// reads a public key line like `ssh-ed25519 AAAAC3Nza... comment` into the SSH wire format
+pub fn ssh_host_key_from_str(line: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let key = line
+        .split_ascii_whitespace()
+        .nth(1)
+        .ok_or("public key line is missing the key")?;
+    Ok(base64::decode(key)?)
+}
+
+// the SSHFP algorithm number (RFC 4255, 6594, 7479, 8709) of a key in SSH wire format
+fn ssh_key_algorithm(host_key: &[u8]) -> Result<u8, Box<dyn std::error::Error>> {
+    if host_key.len() < 4 {
+        return Err("SSH host key is too short".into());
+    }
+    let length = u32::from_be_bytes([host_key[0], host_key[1], host_key[2], host_key[3]]) as usize;
+    let name = host_key
+        .get(4..4 + length)
+        .ok_or("SSH host key is too short")?;
+    match name {
+        b"ssh-rsa" => Ok(1),
+        b"ssh-dss" => Ok(2),
+        name if name.starts_with(b"ecdsa-sha2-") => Ok(3),
+        b"ssh-ed25519" => Ok(4),
+        b"ssh-ed448" => Ok(6),
+        name => Err(format!(
+            "unknown SSH key algorithm {}",
+            String::from_utf8_lossy(name)
+        )
+        .into()),
+    }
+}
+
+// content and remainder of the DER element at the start of some data
+type DerElement<'a> = (&'a [u8], &'a [u8]);
+
+fn der_element(data: &[u8], tag: u8) -> Result<DerElement<'_>, Box<dyn std::error::Error>> {
+    if data.len() < 2 || data[0] != tag {
+        return Err(format!("expected DER element with tag {:#x}", tag).into());
+    }
+    let (length, header) = match data[1] {
+        length @ 0..=0x7f => (length as usize, 2),
+        length_bytes @ 0x81..=0x84 => {
+            let length_bytes = (length_bytes & 0x7f) as usize;
+            let length = data
+                .get(2..2 + length_bytes)
+                .ok_or("DER length is truncated")?
+                .iter()
+                .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+            (length, 2 + length_bytes)
+        }
+        _ => return Err("unsupported DER length".into()),
+    };
+    let content = data
+        .get(header..header + length)
+        .ok_or("DER element is truncated")?;
+    Ok((content, &data[header + length..]))
+}
+
+// the DER encoded SubjectPublicKeyInfo of an X.509 certificate (RFC 5280 4.1)
+pub fn spki_from_certificate(certificate: &[u8]) -> Result<&[u8], Box<dyn std::error::Error>> {
+    let (certificate, _) = der_element(certificate, 0x30)?;
+    let (mut tbs, _) = der_element(certificate, 0x30)?;
+    // the version is optional and explicitly tagged
+    if let Ok((_, rest)) = der_element(tbs, 0xa0) {
+        tbs = rest;
+    }
+    // serial number, signature algorithm, issuer, validity and subject
+    for tag in &[0x02, 0x30, 0x30, 0x30, 0x30] {
+        tbs = der_element(tbs, *tag)?.1;
+    }
+    let (_, rest) = der_element(tbs, 0x30)?;
+    Ok(&tbs[..tbs.len() - rest.len()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut element = vec![tag];
+        if content.len() < 0x80 {
+            element.push(content.len() as u8);
+        } else {
+            element.push(0x82);
+            element.extend((content.len() as u16).to_be_bytes());
+        }
+        element.extend(content);
+        element
+    }
+
+    #[test]
+    fn test_tlsa_matching() {
+        let spki = der(
+            0x30,
+            &[der(0x30, &[0x06, 0x01, 0x2a]), der(0x03, &[0; 200])].concat(),
+        );
+        let tbs = [
+            der(0xa0, &der(0x02, &[2])),
+            der(0x02, &[1]),
+            der(0x30, &[]),
+            der(0x30, &[]),
+            der(0x30, &[]),
+            der(0x30, &[]),
+            spki.clone(),
+        ]
+        .concat();
+        let certificate = der(
+            0x30,
+            &[der(0x30, &tbs), der(0x30, &[]), der(0x03, &[0])].concat(),
+        );
+        assert_eq!(
+            spki_from_certificate(&certificate).unwrap(),
+            spki.as_slice()
+        );
+
+        let record = |selector, matching_type, data: &[u8]| RData::TLSA {
+            usage: 3,
+            selector,
+            matching_type,
+            data: data.to_vec(),
+        };
+        let spki_sha256 = digest(&SHA256, &spki);
+        assert!(record(1, 1, spki_sha256.as_ref())
+            .matches_certificate(&certificate)
+            .unwrap());
+        assert!(record(1, 1, spki_sha256.as_ref())
+            .matches_spki(&spki)
+            .unwrap());
+        assert!(record(0, 0, &certificate)
+            .matches_certificate(&certificate)
+            .unwrap());
+        let certificate_sha512 = digest(&SHA512, &certificate);
+        assert!(record(0, 2, certificate_sha512.as_ref())
+            .matches_certificate(&certificate)
+            .unwrap());
+        assert!(!record(0, 1, spki_sha256.as_ref())
+            .matches_certificate(&certificate)
+            .unwrap());
+        assert!(record(0, 1, spki_sha256.as_ref())
+            .matches_spki(&spki)
+            .is_err());
+        assert!(spki_from_certificate(&certificate[..50]).is_err());
+
+        let rdata =
+            RData::from_wire(52, &record(1, 1, spki_sha256.as_ref()).to_wire().unwrap()).unwrap();
+        assert!(rdata.to_string().starts_with("3 1 1 "));
+    }
+
+    #[test]
+    fn test_sshfp_matching() {
+        let key = ssh_host_key_from_str(
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIBkcy8y3gPqzHOi+hJ8b4lG9IbaI4Y+P+aHnFmQ9/5Cq host",
+        )
+        .unwrap();
+        let sha256 = RData::SSHFP {
+            algorithm: 4,
+            fingerprint_type: 2,
+            fingerprint: digest(&SHA256, &key).as_ref().to_vec(),
+        };
+        assert!(sha256.matches_ssh_host_key(&key).unwrap());
+        let rsa = RData::SSHFP {
+            algorithm: 1,
+            fingerprint_type: 2,
+            fingerprint: digest(&SHA256, &key).as_ref().to_vec(),
+        };
+        assert!(!rsa.matches_ssh_host_key(&key).unwrap());
+
+        let answer = crate::Answer::from_zone_line(&format!(
+            "host.example. 3600 IN SSHFP 4 1 {}",
+            hex::encode(digest(&SHA1_FOR_LEGACY_USE_ONLY, &key))
+        ))
+        .unwrap();
+        assert!(answer
+            .get_rdata()
+            .unwrap()
+            .matches_ssh_host_key(&key)
+            .unwrap());
+        assert!(sha256.matches_certificate(&key).is_err());
+    }
+}
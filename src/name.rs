@@ -0,0 +1,527 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom};
+use std::str::FromStr;
+
+use crate::{FromBytes, ToBytes};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NameError {
+    Idna(String),
+    EmptyLabel,
+    LabelTooLong(String),
+    NameTooLong(usize),
+    InvalidEscape(String),
+    BadPointer(u16),
+    BadLabelType(u8),
+}
+
+impl Display for NameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameError::Idna(errors) => write!(f, "invalid internationalized name: {}", errors),
+            NameError::EmptyLabel => f.write_str("name contains an empty label"),
+            NameError::LabelTooLong(label) => {
+                write!(f, "label {} is longer than 63 bytes", label)
+            }
+            NameError::NameTooLong(length) => {
+                write!(f, "name is {} bytes long, at most 255 are allowed", length)
+            }
+            NameError::InvalidEscape(escape) => write!(f, "invalid escape sequence {}", escape),
+            NameError::BadPointer(ptr) => write!(f, "invalid compression pointer {}", ptr),
+            NameError::BadLabelType(length_type) => {
+                write!(f, "unsupported label type {:#010b}", length_type)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+// An absolute domain name, stored as its labels without the root label. Labels keep the
+// case they were created with, but comparisons and hashing are case-insensitive.
+#[derive(Clone, Default)]
+pub struct Name {
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    pub fn root() -> Name {
+        Name { labels: vec![] }
+    }
+
+    pub fn from_labels<I>(labels: I) -> Result<Name, NameError>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let name = Name {
+            labels: labels.into_iter().collect(),
+        };
+        for label in &name.labels {
+            if label.is_empty() {
+                return Err(NameError::EmptyLabel);
+            }
+            if label.len() > 63 {
+                return Err(NameError::LabelTooLong(
+                    String::from_utf8_lossy(label).to_string(),
+                ));
+            }
+        }
+        if name.wire_len() > 255 {
+            return Err(NameError::NameTooLong(name.wire_len()));
+        }
+        Ok(name)
+    }
+
+    // parses a name given by a user, internationalized names are converted to punycode
+    pub fn from_idna(domain_name: &str) -> Result<Name, NameError> {
+        to_ascii(domain_name)?.parse()
+    }
+
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    pub fn num_labels(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn wire_len(&self) -> usize {
+        self.labels
+            .iter()
+            .map(|label| label.len() + 1)
+            .sum::<usize>()
+            + 1
+    }
+
+    // a name is a subdomain of itself and of all its parents, up to the root
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        self.labels.len() >= other.labels.len()
+            && self
+                .labels
+                .iter()
+                .rev()
+                .zip(other.labels.iter().rev())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    pub fn parent(&self) -> Option<Name> {
+        if self.is_root() {
+            None
+        } else {
+            Some(Name {
+                labels: self.labels[1..].to_vec(),
+            })
+        }
+    }
+
+    pub fn append(&self, suffix: &Name) -> Result<Name, NameError> {
+        Name::from_labels(self.labels.iter().chain(suffix.labels.iter()).cloned())
+    }
+
+    pub fn to_lowercase(&self) -> Name {
+        Name {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| label.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    // DNS 0x20: flips the case of every letter at random, other bytes stay untouched
+    pub fn randomize_case(&self) -> Name {
+        Name {
+            labels: self
+                .labels
+                .iter()
+                .map(|label| {
+                    label
+                        .iter()
+                        .map(|byte| match rand::random::<bool>() {
+                            true => byte.to_ascii_uppercase(),
+                            false => byte.to_ascii_lowercase(),
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    pub fn eq_case_sensitive(&self, other: &Name) -> bool {
+        self.labels == other.labels
+    }
+
+    pub fn to_unicode(&self) -> String {
+        to_unicode(&self.to_string())
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.wire_len());
+        for label in &self.labels {
+            bytes.push(label.len() as u8);
+            bytes.extend(label);
+        }
+        bytes.push(0);
+        bytes
+    }
+}
+
+impl FromStr for Name {
+    type Err = NameError;
+
+    fn from_str(domain_name: &str) -> Result<Self, Self::Err> {
+        if domain_name.is_empty() || domain_name == "." {
+            return Ok(Name::root());
+        }
+        let mut labels = vec![];
+        let mut label = vec![];
+        let mut chars = domain_name.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => labels.push(std::mem::take(&mut label)),
+                '\\' => {
+                    let escaped = chars
+                        .next()
+                        .ok_or_else(|| NameError::InvalidEscape("\\".to_string()))?;
+                    if escaped.is_ascii_digit() {
+                        let digits: String = std::iter::once(escaped)
+                            .chain(chars.by_ref().take(2))
+                            .collect();
+                        let invalid = || NameError::InvalidEscape(format!("\\{}", digits));
+                        if digits.len() != 3 || !digits.chars().all(|c| c.is_ascii_digit()) {
+                            return Err(invalid());
+                        }
+                        label.push(digits.parse::<u8>().map_err(|_| invalid())?);
+                    } else {
+                        let mut buf = [0; 4];
+                        label.extend(escaped.encode_utf8(&mut buf).as_bytes());
+                    }
+                }
+                _ => {
+                    let mut buf = [0; 4];
+                    label.extend(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+        // a missing trailing dot is accepted, all names are treated as absolute
+        if !label.is_empty() {
+            labels.push(label);
+        }
+        Name::from_labels(labels)
+    }
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_root() {
+            return f.write_str(".");
+        }
+        for label in &self.labels {
+            for byte in label {
+                match byte {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                        write!(f, "\\{}", *byte as char)?
+                    }
+                    0x21..=0x7e => write!(f, "{}", *byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+            f.write_str(".")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Name").field(&self.to_string()).finish()
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len() && self.is_subdomain_of(other)
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.labels.len());
+        for label in &self.labels {
+            state.write(&label.to_ascii_lowercase());
+        }
+    }
+}
+
+// canonical DNS name order as defined in RFC 4034, section 6.1
+impl Ord for Name {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for (a, b) in self.labels.iter().rev().zip(other.labels.iter().rev()) {
+            match a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        self.labels.len().cmp(&other.labels.len())
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromBytes for Name {
+    type Deserialized = Name;
+
+    fn read<R>(bytes: &mut R) -> Result<Self::Deserialized, Box<dyn std::error::Error>>
+    where
+        R: Read + Seek,
+    {
+        let mut labels = vec![];
+        let mut wire_len = 1;
+        let mut return_position = None;
+        let mut label_start = bytes.stream_position()?;
+        loop {
+            let length_type = u8::read(bytes)?;
+            match length_type & 0b1100_0000 {
+                0b0000_0000 if length_type == 0 => break,
+                0b0000_0000 => {
+                    let mut label = vec![0; length_type as usize];
+                    bytes.read_exact(&mut label)?;
+                    wire_len += label.len() + 1;
+                    if wire_len > 255 {
+                        return Err(NameError::NameTooLong(wire_len).into());
+                    }
+                    labels.push(label);
+                }
+                0b1100_0000 => {
+                    let ptr = u16::from_be_bytes([length_type & 0b0011_1111, u8::read(bytes)?]);
+                    // only pointing backwards guarantees that decompression terminates
+                    if ptr as u64 >= label_start {
+                        return Err(NameError::BadPointer(ptr).into());
+                    }
+                    if return_position.is_none() {
+                        return_position = Some(bytes.stream_position()?);
+                    }
+                    bytes.seek(SeekFrom::Start(ptr as u64))?;
+                }
+                _ => return Err(NameError::BadLabelType(length_type).into()),
+            }
+            label_start = bytes.stream_position()?;
+        }
+        if let Some(position) = return_position {
+            bytes.seek(SeekFrom::Start(position))?;
+        }
+        Ok(Name { labels })
+    }
+}
+
+impl ToBytes for Name {
+    fn write<W>(&self, bytes: &mut W) -> Result<(), Box<dyn std::error::Error>>
+    where
+        W: std::io::Write,
+    {
+        bytes.write_all(&self.to_vec())?;
+        Ok(())
+    }
+}
+
+fn idna_config() -> idna::Config {
+    idna::Config::default()
+        .use_std3_ascii_rules(false)
+        .transitional_processing(false)
+        .check_hyphens(false)
+        .use_idna_2008_rules(true)
+}
+
+// converts a (possibly internationalized) domain name to its ASCII form using UTS #46
+// mapping and punycode. Underscores and wildcards stay allowed, as they are common in DNS,
+// and ASCII names keep their case.
+pub fn to_ascii(domain_name: &str) -> Result<String, NameError> {
+    if domain_name.is_empty() || domain_name == "." {
+        return Ok(domain_name.to_string());
+    }
+    let ascii = idna_config()
+        .to_ascii(domain_name)
+        .map_err(|errors| NameError::Idna(errors.to_string()))?;
+    if ascii.eq_ignore_ascii_case(domain_name) {
+        Ok(domain_name.to_string())
+    } else {
+        Ok(ascii)
+    }
+}
+
+// converts `xn--` labels back to Unicode, names that can't be decoded are returned unchanged
+pub fn to_unicode(domain_name: &str) -> String {
+    match idna_config().to_unicode(domain_name) {
+        (unicode, Ok(())) => unicode,
+        (_, Err(_)) => domain_name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::io::Cursor;
+
+    use crate::{DnsPacket, FromBytes, RecordType};
+
+    use super::*;
+
+    #[test]
+    fn test_idna_round_trip() {
+        assert_eq!(to_ascii("Bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(
+            to_ascii("_dmarc.Example.com.").unwrap(),
+            "_dmarc.Example.com."
+        );
+        assert_eq!(to_unicode("xn--bcher-kva.example."), "bücher.example.");
+        assert_eq!(to_unicode("xn--zz.example"), "xn--zz.example");
+
+        let pkg = DnsPacket::builder()
+            .add_query("bücher.example", RecordType::A)
+            .unwrap()
+            .build();
+        assert_eq!(pkg.queries[0].name.to_string(), "xn--bcher-kva.example.");
+        assert_eq!(pkg.queries[0].name.to_unicode(), "bücher.example.");
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        assert_eq!(Name::from_idna("a..example"), Err(NameError::EmptyLabel));
+        assert!(matches!(
+            Name::from_idna(&format!("{}.example", "a".repeat(64))),
+            Err(NameError::LabelTooLong(_))
+        ));
+        assert!(matches!(
+            Name::from_idna(&vec!["a".repeat(60); 5].join(".")),
+            Err(NameError::NameTooLong(306))
+        ));
+        assert!(matches!(to_ascii("xn--a.example"), Err(NameError::Idna(_))));
+        assert!(matches!(
+            "a\\25x.example".parse::<Name>(),
+            Err(NameError::InvalidEscape(_))
+        ));
+        assert!(DnsPacket::builder()
+            .add_query("a..example", RecordType::A)
+            .is_err());
+    }
+
+    #[test]
+    fn test_escaping() {
+        let name: Name = "a\\.b\\000c.Example.".parse().unwrap();
+        assert_eq!(name.labels()[0], b"a.b\0c".to_vec());
+        assert_eq!(name.num_labels(), 2);
+        assert_eq!(name.to_string(), "a\\.b\\000c.Example.");
+        assert_eq!(
+            name.to_string().parse::<Name>().unwrap().labels(),
+            name.labels()
+        );
+        assert_eq!(Name::root().to_string(), ".");
+        assert_eq!(".".parse::<Name>().unwrap(), Name::root());
+    }
+
+    #[test]
+    fn test_comparison_and_hierarchy() {
+        let name: Name = "WWW.Example.com".parse().unwrap();
+        let zone: Name = "example.COM.".parse().unwrap();
+        assert_eq!(name.parent().unwrap(), zone);
+        assert!(name.is_subdomain_of(&zone));
+        assert!(name.is_subdomain_of(&Name::root()));
+        assert!(!zone.is_subdomain_of(&name));
+        assert_eq!("www".parse::<Name>().unwrap().append(&zone).unwrap(), name);
+        let set: HashSet<Name> = vec![name.clone(), name.to_lowercase()]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 1);
+        // the example from RFC 4034, section 6.1
+        let mut names: Vec<Name> = vec![
+            "z.example.",
+            "a.example.",
+            "\\001.z.example.",
+            "yljkjljk.a.example.",
+            "*.z.example.",
+            "Z.a.example.",
+            "\\200.z.example.",
+            "example.",
+            "zABC.a.EXAMPLE.",
+        ]
+        .into_iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+        names.sort();
+        let sorted: Vec<String> = names.iter().map(Name::to_string).collect();
+        assert_eq!(
+            sorted,
+            vec![
+                "example.",
+                "a.example.",
+                "yljkjljk.a.example.",
+                "Z.a.example.",
+                "zABC.a.EXAMPLE.",
+                "z.example.",
+                "\\001.z.example.",
+                "*.z.example.",
+                "\\200.z.example.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_0x20_case_randomization() {
+        let mut query = DnsPacket::builder()
+            .add_query(
+                "a-very-long-label-with-many-letters.example.com",
+                RecordType::A,
+            )
+            .unwrap()
+            .build();
+        let original = query.queries[0].name.clone();
+        query.randomize_query_case();
+        let randomized = query.queries[0].name.clone();
+        assert_eq!(randomized, original);
+        assert!(!randomized.eq_case_sensitive(&original));
+        assert_eq!(
+            randomized.labels()[0]
+                .iter()
+                .filter(|b| **b == b'-')
+                .count(),
+            6
+        );
+
+        let echo = DnsPacket::read(&mut Cursor::new(query.to_vec().unwrap())).unwrap();
+        assert!(query.check_question_echo(&echo).is_ok());
+
+        let mut lowercased = DnsPacket::read(&mut Cursor::new(query.to_vec().unwrap())).unwrap();
+        lowercased.queries[0].name = lowercased.queries[0].name.to_lowercase();
+        assert!(query.check_question_echo(&lowercased).is_err());
+    }
+
+    #[test]
+    fn test_compression_pointers() {
+        // "example." at offset 0, "www" + pointer to offset 0 at offset 9
+        let bytes = b"\x07example\x00\x03www\xc0\x00\xc0\x0f".to_vec();
+        let mut cursor = Cursor::new(&bytes);
+        cursor.set_position(9);
+        let name = Name::read(&mut cursor).unwrap();
+        assert_eq!(name.to_string(), "www.example.");
+        assert_eq!(cursor.position(), 15);
+        // a pointer to itself must not loop forever
+        assert!(Name::read(&mut cursor).is_err());
+    }
+}
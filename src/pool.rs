@@ -0,0 +1,112 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::BufReader;
+use tokio::net::TcpStream;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio_rustls::client::TlsStream;
+
+// an open HTTP/1.1 connection, the permit counts it against the limit of its host
+pub(crate) struct PooledConnection {
+    pub(crate) stream: BufReader<TlsStream<TcpStream>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    pub(crate) fn new(stream: TlsStream<TcpStream>, permit: OwnedSemaphorePermit) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            _permit: permit,
+        }
+    }
+}
+
+// an idle connection or the permit to open a new one
+pub(crate) enum Checkout {
+    Idle(Box<PooledConnection>),
+    New(OwnedSemaphorePermit),
+}
+
+struct IdleConnection {
+    connection: PooledConnection,
+    since: Instant,
+}
+
+struct Host {
+    idle: Vec<IdleConnection>,
+    permits: Arc<Semaphore>,
+    // idle connections hold on to their permits, so waiters are woken when one is returned
+    returned: Arc<Notify>,
+}
+
+// keep-alive connections per host, idle ones are closed after `idle_timeout`
+pub(crate) struct Pool {
+    pub(crate) max_connections: usize,
+    pub(crate) idle_timeout: Duration,
+    hosts: Mutex<HashMap<String, Host>>,
+}
+
+impl Pool {
+    pub(crate) fn new(max_connections: usize, idle_timeout: Duration) -> Self {
+        Self {
+            max_connections,
+            idle_timeout,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // waits until a connection is idle or the host is below its connection limit
+    pub(crate) async fn get(&self, key: &str) -> Result<Checkout, Box<dyn std::error::Error>> {
+        let (permits, returned) = self.host(key);
+        loop {
+            if let Some(connection) = self.checkout(key) {
+                return Ok(Checkout::Idle(Box::new(connection)));
+            }
+            tokio::select! {
+                permit = permits.clone().acquire_owned() => return Ok(Checkout::New(permit?)),
+                _ = returned.notified() => {}
+            }
+        }
+    }
+
+    // the most recently used idle connection that has not timed out
+    fn checkout(&self, key: &str) -> Option<PooledConnection> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.get_mut(key)?;
+        let idle_timeout = self.idle_timeout;
+        host.idle.retain(|idle| idle.since.elapsed() < idle_timeout);
+        host.idle.pop().map(|idle| idle.connection)
+    }
+
+    pub(crate) fn checkin(&self, key: &str, connection: PooledConnection) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(host) = hosts.get_mut(key) {
+            host.idle.push(IdleConnection {
+                connection,
+                since: Instant::now(),
+            });
+            host.returned.notify_one();
+        }
+    }
+
+    pub(crate) fn try_acquire(&self, key: &str) -> Option<OwnedSemaphorePermit> {
+        self.host(key).0.try_acquire_owned().ok()
+    }
+
+    fn host(&self, key: &str) -> (Arc<Semaphore>, Arc<Notify>) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let max_connections = self.max_connections;
+        let host = hosts.entry(key.to_string()).or_insert_with(|| Host {
+            idle: vec![],
+            permits: Arc::new(Semaphore::new(max_connections)),
+            returned: Arc::new(Notify::new()),
+        });
+        (host.permits.clone(), host.returned.clone())
+    }
+}
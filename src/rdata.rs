@@ -0,0 +1,752 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::io::{Cursor, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::loc::Loc;
+use crate::name::Name;
+use crate::txt::TxtData;
+use crate::zone::{
+    character_string_to_string, class_from_str, class_to_string, generic_rdata_to_string,
+    type_from_str, type_to_string,
+};
+use crate::FromBytes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    A,
+    NS,
+    CNAME,
+    SOA,
+    PTR,
+    HINFO,
+    MX,
+    TXT,
+    RP,
+    AAAA,
+    LOC,
+    NAPTR,
+    DNAME,
+    OPT,
+    SSHFP,
+    TLSA,
+    ANY,
+    URI,
+    CAA,
+    // only for types without a variant, `RecordType::from` maps known codes to their variants
+    Unknown(u16),
+}
+
+const RECORD_TYPES: [(RecordType, u16); 19] = [
+    (RecordType::A, 1),
+    (RecordType::NS, 2),
+    (RecordType::CNAME, 5),
+    (RecordType::SOA, 6),
+    (RecordType::PTR, 12),
+    (RecordType::HINFO, 13),
+    (RecordType::MX, 15),
+    (RecordType::TXT, 16),
+    (RecordType::RP, 17),
+    (RecordType::AAAA, 28),
+    (RecordType::LOC, 29),
+    (RecordType::NAPTR, 35),
+    (RecordType::DNAME, 39),
+    (RecordType::OPT, 41),
+    (RecordType::SSHFP, 44),
+    (RecordType::TLSA, 52),
+    (RecordType::ANY, 255),
+    (RecordType::URI, 256),
+    (RecordType::CAA, 257),
+];
+
+impl RecordType {
+    pub fn to_short(&self) -> u16 {
+        match self {
+            RecordType::Unknown(ty) => *ty,
+            known => {
+                RECORD_TYPES
+                    .iter()
+                    .find(|(record_type, _)| record_type == known)
+                    .unwrap()
+                    .1
+            }
+        }
+    }
+}
+
+impl From<u16> for RecordType {
+    fn from(ty: u16) -> Self {
+        RECORD_TYPES
+            .iter()
+            .find(|(_, value)| *value == ty)
+            .map(|(record_type, _)| *record_type)
+            .unwrap_or(RecordType::Unknown(ty))
+    }
+}
+
+impl From<RecordType> for u16 {
+    fn from(ty: RecordType) -> Self {
+        ty.to_short()
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(RecordType::ANY);
+        }
+        Ok(RecordType::from(type_from_str(s)?))
+    }
+}
+
+impl Display for RecordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", type_to_string(self.to_short()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordClass {
+    IN,
+    CH,
+    HS,
+    NONE,
+    ANY,
+    Unknown(u16),
+}
+
+impl RecordClass {
+    pub fn to_short(&self) -> u16 {
+        match self {
+            RecordClass::IN => 1,
+            RecordClass::CH => 3,
+            RecordClass::HS => 4,
+            RecordClass::NONE => 254,
+            RecordClass::ANY => 255,
+            RecordClass::Unknown(class) => *class,
+        }
+    }
+}
+
+impl From<u16> for RecordClass {
+    fn from(class: u16) -> Self {
+        match class {
+            1 => RecordClass::IN,
+            3 => RecordClass::CH,
+            4 => RecordClass::HS,
+            254 => RecordClass::NONE,
+            255 => RecordClass::ANY,
+            class => RecordClass::Unknown(class),
+        }
+    }
+}
+
+impl From<RecordClass> for u16 {
+    fn from(class: RecordClass) -> Self {
+        class.to_short()
+    }
+}
+
+impl FromStr for RecordClass {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("CHAOS") {
+            return Ok(RecordClass::CH);
+        }
+        Ok(RecordClass::from(class_from_str(s)?))
+    }
+}
+
+impl Display for RecordClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", class_to_string(self.to_short()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    NS(Name),
+    CNAME(Name),
+    PTR(Name),
+    DNAME(Name),
+    MX {
+        preference: u16,
+        exchange: Name,
+    },
+    TXT(TxtData),
+    HINFO {
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    },
+    RP {
+        mbox: Name,
+        txt: Name,
+    },
+    NAPTR {
+        order: u16,
+        preference: u16,
+        flags: Vec<u8>,
+        services: Vec<u8>,
+        regexp: Vec<u8>,
+        replacement: Name,
+    },
+    LOC(Loc),
+    URI {
+        priority: u16,
+        weight: u16,
+        target: Vec<u8>,
+    },
+    CAA {
+        flags: u8,
+        tag: Vec<u8>,
+        value: Vec<u8>,
+    },
+    SOA {
+        mname: Name,
+        rname: Name,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    SSHFP {
+        algorithm: u8,
+        fingerprint_type: u8,
+        fingerprint: Vec<u8>,
+    },
+    TLSA {
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        data: Vec<u8>,
+    },
+    // rdata of types we don't decode is kept as is
+    Unknown(u16, Vec<u8>),
+}
+
+impl RData {
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            RData::A(_) => RecordType::A,
+            RData::AAAA(_) => RecordType::AAAA,
+            RData::NS(_) => RecordType::NS,
+            RData::CNAME(_) => RecordType::CNAME,
+            RData::PTR(_) => RecordType::PTR,
+            RData::DNAME(_) => RecordType::DNAME,
+            RData::MX { .. } => RecordType::MX,
+            RData::TXT(_) => RecordType::TXT,
+            RData::HINFO { .. } => RecordType::HINFO,
+            RData::RP { .. } => RecordType::RP,
+            RData::NAPTR { .. } => RecordType::NAPTR,
+            RData::LOC(_) => RecordType::LOC,
+            RData::URI { .. } => RecordType::URI,
+            RData::CAA { .. } => RecordType::CAA,
+            RData::SOA { .. } => RecordType::SOA,
+            RData::SSHFP { .. } => RecordType::SSHFP,
+            RData::TLSA { .. } => RecordType::TLSA,
+            RData::Unknown(ty, _) => RecordType::from(*ty),
+        }
+    }
+
+    // expects uncompressed rdata, as it is stored in `Answer`
+    pub fn from_wire(ty: u16, data: &[u8]) -> Result<RData, Box<dyn std::error::Error>> {
+        let mut bytes = Cursor::new(data);
+        let rdata = match RecordType::from(ty) {
+            RecordType::A => {
+                if data.len() != 4 {
+                    return Err("A record should have 4 bytes".into());
+                }
+                let mut octets = [0; 4];
+                bytes.read_exact(&mut octets)?;
+                RData::A(Ipv4Addr::from(octets))
+            }
+            RecordType::AAAA => {
+                if data.len() != 16 {
+                    return Err("AAAA record should have 16 bytes".into());
+                }
+                let mut octets = [0; 16];
+                bytes.read_exact(&mut octets)?;
+                RData::AAAA(Ipv6Addr::from(octets))
+            }
+            RecordType::NS => RData::NS(Name::read(&mut bytes)?),
+            RecordType::CNAME => RData::CNAME(Name::read(&mut bytes)?),
+            RecordType::PTR => RData::PTR(Name::read(&mut bytes)?),
+            RecordType::DNAME => RData::DNAME(Name::read(&mut bytes)?),
+            RecordType::MX => RData::MX {
+                preference: u16::read(&mut bytes)?,
+                exchange: Name::read(&mut bytes)?,
+            },
+            RecordType::TXT => return Ok(RData::TXT(TxtData::from_rdata(data)?)),
+            RecordType::LOC => return Ok(RData::LOC(Loc::from_rdata(data)?)),
+            RecordType::HINFO => RData::HINFO {
+                cpu: read_character_string(&mut bytes)?,
+                os: read_character_string(&mut bytes)?,
+            },
+            RecordType::RP => RData::RP {
+                mbox: Name::read(&mut bytes)?,
+                txt: Name::read(&mut bytes)?,
+            },
+            RecordType::NAPTR => RData::NAPTR {
+                order: u16::read(&mut bytes)?,
+                preference: u16::read(&mut bytes)?,
+                flags: read_character_string(&mut bytes)?,
+                services: read_character_string(&mut bytes)?,
+                regexp: read_character_string(&mut bytes)?,
+                replacement: Name::read(&mut bytes)?,
+            },
+            RecordType::URI if data.len() >= 4 => {
+                return Ok(RData::URI {
+                    priority: u16::from_be_bytes([data[0], data[1]]),
+                    weight: u16::from_be_bytes([data[2], data[3]]),
+                    target: data[4..].to_vec(),
+                })
+            }
+            RecordType::CAA if data.len() >= 2 && data.len() >= 2 + data[1] as usize => {
+                let tag_end = 2 + data[1] as usize;
+                return Ok(RData::CAA {
+                    flags: data[0],
+                    tag: data[2..tag_end].to_vec(),
+                    value: data[tag_end..].to_vec(),
+                });
+            }
+            RecordType::SOA => RData::SOA {
+                mname: Name::read(&mut bytes)?,
+                rname: Name::read(&mut bytes)?,
+                serial: u32::read(&mut bytes)?,
+                refresh: u32::read(&mut bytes)?,
+                retry: u32::read(&mut bytes)?,
+                expire: u32::read(&mut bytes)?,
+                minimum: u32::read(&mut bytes)?,
+            },
+            RecordType::SSHFP if data.len() >= 2 => {
+                return Ok(RData::SSHFP {
+                    algorithm: data[0],
+                    fingerprint_type: data[1],
+                    fingerprint: data[2..].to_vec(),
+                })
+            }
+            RecordType::TLSA if data.len() >= 3 => {
+                return Ok(RData::TLSA {
+                    usage: data[0],
+                    selector: data[1],
+                    matching_type: data[2],
+                    data: data[3..].to_vec(),
+                })
+            }
+            RecordType::SSHFP | RecordType::TLSA | RecordType::URI | RecordType::CAA => {
+                return Err(format!("{} rdata is too short", RecordType::from(ty)).into())
+            }
+            _ => return Ok(RData::Unknown(ty, data.to_vec())),
+        };
+        if bytes.position() as usize != data.len() {
+            return Err(format!("{} rdata has trailing bytes", RecordType::from(ty)).into());
+        }
+        Ok(rdata)
+    }
+
+    pub fn to_wire(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut data = vec![];
+        match self {
+            RData::A(ip) => data.extend(ip.octets()),
+            RData::AAAA(ip) => data.extend(ip.octets()),
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) | RData::DNAME(name) => {
+                data.extend(name.to_vec())
+            }
+            RData::MX {
+                preference,
+                exchange,
+            } => {
+                data.extend(preference.to_be_bytes());
+                data.extend(exchange.to_vec());
+            }
+            RData::TXT(txt) => data.extend(txt.to_rdata()),
+            RData::LOC(loc) => data.extend(loc.to_rdata()),
+            RData::HINFO { cpu, os } => {
+                write_character_string(&mut data, cpu)?;
+                write_character_string(&mut data, os)?;
+            }
+            RData::RP { mbox, txt } => {
+                data.extend(mbox.to_vec());
+                data.extend(txt.to_vec());
+            }
+            RData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => {
+                data.extend(order.to_be_bytes());
+                data.extend(preference.to_be_bytes());
+                write_character_string(&mut data, flags)?;
+                write_character_string(&mut data, services)?;
+                write_character_string(&mut data, regexp)?;
+                data.extend(replacement.to_vec());
+            }
+            RData::URI {
+                priority,
+                weight,
+                target,
+            } => {
+                data.extend(priority.to_be_bytes());
+                data.extend(weight.to_be_bytes());
+                data.extend(target);
+            }
+            RData::CAA { flags, tag, value } => {
+                data.push(*flags);
+                write_character_string(&mut data, tag)?;
+                data.extend(value);
+            }
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                data.extend(mname.to_vec());
+                data.extend(rname.to_vec());
+                for number in &[serial, refresh, retry, expire, minimum] {
+                    data.extend(number.to_be_bytes());
+                }
+            }
+            RData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => {
+                data.extend([*algorithm, *fingerprint_type]);
+                data.extend(fingerprint);
+            }
+            RData::TLSA {
+                usage,
+                selector,
+                matching_type,
+                data: association,
+            } => {
+                data.extend([*usage, *selector, *matching_type]);
+                data.extend(association);
+            }
+            RData::Unknown(_, rdata) => data.extend(rdata),
+        }
+        Ok(data)
+    }
+}
+
+fn read_character_string(bytes: &mut Cursor<&[u8]>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut string = vec![0; u8::read(bytes)? as usize];
+    bytes.read_exact(&mut string)?;
+    Ok(string)
+}
+
+fn write_character_string(
+    data: &mut Vec<u8>,
+    string: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if string.len() > 255 {
+        return Err("character-string is longer than 255 bytes".into());
+    }
+    data.push(string.len() as u8);
+    data.extend(string);
+    Ok(())
+}
+
+impl Display for RData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RData::A(ip) => write!(f, "{}", ip),
+            RData::AAAA(ip) => write!(f, "{}", ip),
+            RData::NS(name) | RData::CNAME(name) | RData::PTR(name) | RData::DNAME(name) => {
+                write!(f, "{}", name)
+            }
+            RData::MX {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            RData::TXT(txt) => write!(f, "{}", txt),
+            RData::LOC(loc) => write!(f, "{}", loc),
+            RData::HINFO { cpu, os } => write!(
+                f,
+                "{} {}",
+                character_string_to_string(cpu),
+                character_string_to_string(os)
+            ),
+            RData::RP { mbox, txt } => write!(f, "{} {}", mbox, txt),
+            RData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => write!(
+                f,
+                "{} {} {} {} {} {}",
+                order,
+                preference,
+                character_string_to_string(flags),
+                character_string_to_string(services),
+                character_string_to_string(regexp),
+                replacement
+            ),
+            RData::URI {
+                priority,
+                weight,
+                target,
+            } => write!(
+                f,
+                "{} {} {}",
+                priority,
+                weight,
+                character_string_to_string(target)
+            ),
+            RData::CAA { flags, tag, value } => write!(
+                f,
+                "{} {} {}",
+                flags,
+                String::from_utf8_lossy(tag),
+                character_string_to_string(value)
+            ),
+            RData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            RData::SSHFP {
+                algorithm,
+                fingerprint_type,
+                fingerprint,
+            } => write!(
+                f,
+                "{} {} {}",
+                algorithm,
+                fingerprint_type,
+                hex::encode_upper(fingerprint)
+            ),
+            RData::TLSA {
+                usage,
+                selector,
+                matching_type,
+                data,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                usage,
+                selector,
+                matching_type,
+                hex::encode_upper(data)
+            ),
+            RData::Unknown(_, data) => write!(f, "{}", generic_rdata_to_string(data)),
+        }
+    }
+}
+
+impl From<Ipv4Addr> for RData {
+    fn from(ip: Ipv4Addr) -> Self {
+        RData::A(ip)
+    }
+}
+
+impl From<Ipv6Addr> for RData {
+    fn from(ip: Ipv6Addr) -> Self {
+        RData::AAAA(ip)
+    }
+}
+
+impl From<IpAddr> for RData {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => RData::A(ip),
+            IpAddr::V6(ip) => RData::AAAA(ip),
+        }
+    }
+}
+
+impl TryFrom<RData> for IpAddr {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(rdata: RData) -> Result<Self, Self::Error> {
+        match rdata {
+            RData::A(ip) => Ok(IpAddr::V4(ip)),
+            RData::AAAA(ip) => Ok(IpAddr::V6(ip)),
+            other => Err(format!("{} is not an address record", other.record_type()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Answer, DnsPacket};
+
+    use super::*;
+
+    #[test]
+    fn test_record_type_conversions() {
+        assert_eq!(RecordType::SOA.to_short(), 6);
+        assert_eq!(RecordType::from(28), RecordType::AAAA);
+        assert_eq!(RecordType::from(65534), RecordType::Unknown(65534));
+        assert_eq!(u16::from(RecordType::Unknown(65534)), 65534);
+        assert_eq!("mx".parse::<RecordType>().unwrap(), RecordType::MX);
+        assert_eq!("*".parse::<RecordType>().unwrap(), RecordType::ANY);
+        assert_eq!("TYPE1".parse::<RecordType>().unwrap(), RecordType::A);
+        assert_eq!(RecordType::Unknown(65534).to_string(), "TYPE65534");
+        // a known type never comes out as `Unknown`, whichever way it is read
+        assert_eq!("TYPE13".parse::<RecordType>().unwrap(), RecordType::HINFO);
+        for ty in 0..=u16::MAX {
+            if let RecordType::Unknown(unknown) = RecordType::from(ty) {
+                assert!(RECORD_TYPES.iter().all(|(_, known)| *known != unknown));
+            }
+        }
+        let answer = Answer::read(&mut Cursor::new(&[
+            0, 0, 13, 0, 1, 0, 0, 0, 60, 0, 4, 1, b'a', 1, b'b',
+        ]))
+        .unwrap();
+        assert_eq!(answer.get_record_type(), RecordType::HINFO);
+        assert!("BOGUS".parse::<RecordType>().is_err());
+    }
+
+    #[test]
+    fn test_record_class_conversions() {
+        assert_eq!(RecordClass::from(3), RecordClass::CH);
+        assert_eq!(RecordClass::HS.to_short(), 4);
+        assert_eq!("chaos".parse::<RecordClass>().unwrap(), RecordClass::CH);
+        assert_eq!(
+            "CLASS254".parse::<RecordClass>().unwrap(),
+            RecordClass::NONE
+        );
+        assert_eq!(RecordClass::Unknown(42).to_string(), "CLASS42");
+        assert!("XX".parse::<RecordClass>().is_err());
+    }
+
+    #[test]
+    fn test_address_conversions() {
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let rdata = RData::from(ip);
+        assert_eq!(rdata.record_type(), RecordType::AAAA);
+        let answer = Answer::from_record("a.example".parse().unwrap(), 60, rdata).unwrap();
+        let ip = IpAddr::try_from(answer.get_rdata().unwrap()).unwrap();
+        assert_eq!(ip.to_string(), "2001:db8::1");
+        let socket = std::net::SocketAddr::new(ip, 853);
+        assert_eq!(socket.to_string(), "[2001:db8::1]:853");
+        assert!(IpAddr::try_from(RData::TXT(TxtData::default())).is_err());
+    }
+
+    #[test]
+    fn test_soa_round_trip() {
+        let soa = RData::SOA {
+            mname: "ns1.example.com".parse().unwrap(),
+            rname: "hostmaster.example.com".parse().unwrap(),
+            serial: 2021090101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        };
+        let data = soa.to_wire().unwrap();
+        assert_eq!(RData::from_wire(6, &data).unwrap(), soa);
+        assert_eq!(
+            soa.to_string(),
+            "ns1.example.com. hostmaster.example.com. 2021090101 7200 3600 1209600 300"
+        );
+        assert!(RData::from_wire(6, &data[..data.len() - 1]).is_err());
+        assert!(RData::from_wire(1, &[127, 0, 0]).is_err());
+    }
+
+    fn sample_rdata(sample: &str) -> Vec<(RData, String)> {
+        let bytes = base64::decode(sample).unwrap();
+        let packet = DnsPacket::read(&mut Cursor::new(&bytes)).unwrap();
+        packet
+            .answers
+            .iter()
+            .map(|answer| {
+                let rdata = answer.get_rdata().unwrap();
+                // encoding the decoded rdata gives back the (uncompressed) wire data
+                let data = rdata.to_wire().unwrap();
+                assert_eq!(
+                    RData::from_wire(answer.get_record_type().into(), &data).unwrap(),
+                    rdata
+                );
+                // and the presentation form can be read back as a zone line
+                let line = answer.to_string();
+                assert_eq!(Answer::from_zone_line(&line).unwrap().to_string(), line);
+                let text = rdata.to_string();
+                (rdata, text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_naptr() {
+        let records = sample_rdata("EjSBgAABAAIAAAAAB2V4YW1wbGUDY29tAAAjAAHADAAjAAEAAA4QACYAZAAKAVMHU0lQK0QyVQAEX3NpcARfdWRwB2V4YW1wbGUDY29tAMAMACMAAQAADhAAKwBkABQBVQdFMlUrc2lwGyFeLiokIXNpcDppbmZvQGV4YW1wbGUuY29tIQA=");
+        assert!(matches!(
+            &records[0].0,
+            RData::NAPTR { order: 100, preference: 10, flags, services, regexp, replacement }
+                if flags == b"S" && services == b"SIP+D2U" && regexp.is_empty()
+                    && replacement.to_string() == "_sip._udp.example.com."
+        ));
+        assert_eq!(
+            records[0].1,
+            r#"100 10 "S" "SIP+D2U" "" _sip._udp.example.com."#
+        );
+        assert_eq!(
+            records[1].1,
+            r#"100 20 "U" "E2U+sip" "!^.*$!sip:info@example.com!" ."#
+        );
+    }
+
+    #[test]
+    fn test_uri_hinfo_rp() {
+        let records = sample_rdata("EjSBgAABAAEAAAAABF9mdHAEX3RjcAdleGFtcGxlA2NvbQABAAABwAwBAAABAAAOEAAhAAoAAWZ0cDovL2Z0cDEuZXhhbXBsZS5jb20vcHVibGlj");
+        assert!(matches!(
+            &records[0].0,
+            RData::URI { priority: 10, weight: 1, target } if target == b"ftp://ftp1.example.com/public"
+        ));
+        assert_eq!(records[0].1, r#"10 1 "ftp://ftp1.example.com/public""#);
+
+        let records = sample_rdata(
+            "EjSBgAABAAEAAAAABGhvc3QHZXhhbXBsZQNjb20AAA0AAcAMAA0AAQAADhAAFQlJTlRFTC0zODYKV2luZG93cyBOVA==",
+        );
+        assert_eq!(records[0].1, r#""INTEL-386" "Windows NT""#);
+
+        // the txt name of this RP record is compressed
+        let records = sample_rdata(
+            "EjSBgAABAAEAAAAAB2V4YW1wbGUDY29tAAARAAHADAARAAEAAA4QABUFYWRtaW4HZXhhbXBsZQNjb20AwAw=",
+        );
+        assert_eq!(records[0].1, "admin.example.com. example.com.");
+    }
+
+    #[test]
+    fn test_caa() {
+        let records = sample_rdata("EjSBgAABAAIAAAAAB2V4YW1wbGUDY29tAAEBAAHADAEBAAEAAA4QABYABWlzc3VlbGV0c2VuY3J5cHQub3JnwAwBAQABAAAOEAAigAVpb2RlZm1haWx0bzpzZWN1cml0eUBleGFtcGxlLmNvbQ==");
+        assert!(matches!(
+            &records[0].0,
+            RData::CAA { flags: 0, tag, value } if tag == b"issue" && value == b"letsencrypt.org"
+        ));
+        assert_eq!(records[0].1, r#"0 issue "letsencrypt.org""#);
+        assert_eq!(records[1].1, r#"128 iodef "mailto:security@example.com""#);
+        assert!(RData::from_wire(257, &[0, 5, b'i']).is_err());
+    }
+}
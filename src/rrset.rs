@@ -0,0 +1,247 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use crate::name::Name;
+use crate::{Answer, DnsPacket, RData, RecordClass, RecordType};
+
+// all records of a section with the same name, type and class. RFC 2181 5.2 requires them to
+// share a TTL, if they don't the smallest one is used
+#[derive(Debug, Clone)]
+pub struct RRset {
+    pub name: Name,
+    pub ty: RecordType,
+    pub class: RecordClass,
+    pub ttl: u32,
+    pub records: Vec<Answer>,
+}
+
+impl RRset {
+    pub fn rdata(&self) -> Result<Vec<RData>, Box<dyn std::error::Error>> {
+        self.records
+            .iter()
+            .map(|record| record.get_rdata())
+            .collect()
+    }
+}
+
+// groups records into RRsets in the order they first appear, the OPT pseudo record is skipped
+pub fn group_rrsets(records: &[Answer]) -> Vec<RRset> {
+    let mut rrsets: Vec<RRset> = vec![];
+    for record in records {
+        let (ty, class) = (record.get_record_type(), record.get_class());
+        if ty == RecordType::OPT {
+            continue;
+        }
+        match rrsets
+            .iter_mut()
+            .find(|rrset| rrset.ty == ty && rrset.class == class && rrset.name == record.name)
+        {
+            Some(rrset) => {
+                rrset.ttl = rrset.ttl.min(record.ttl);
+                rrset.records.push(record.clone());
+            }
+            None => rrsets.push(RRset {
+                name: record.name.clone(),
+                ty,
+                class,
+                ttl: record.ttl,
+                records: vec![record.clone()],
+            }),
+        }
+    }
+    rrsets
+}
+
+impl DnsPacket {
+    pub fn answer_rrsets(&self) -> Vec<RRset> {
+        group_rrsets(&self.answers)
+    }
+
+    pub fn authority_rrsets(&self) -> Vec<RRset> {
+        group_rrsets(&self.authorities)
+    }
+
+    pub fn additional_rrsets(&self) -> Vec<RRset> {
+        group_rrsets(&self.additional_options)
+    }
+
+    // the names from the question name to the final target, following CNAME and DNAME records in
+    // the answer section. A CNAME synthesized from a DNAME is preferred over the substitution
+    pub fn resolve_chain(&self) -> Result<Vec<Name>, Box<dyn std::error::Error>> {
+        let query = self.queries.first().ok_or("packet has no question")?;
+        let class = query.get_class();
+        let mut chain = vec![query.name.clone()];
+        let mut seen: HashSet<Name> = chain.iter().cloned().collect();
+        // a CNAME query asks for the alias itself, so it is not followed
+        if query.get_record_type() == RecordType::CNAME {
+            return Ok(chain);
+        }
+        let mut current = query.name.clone();
+        while let Some(next) = self.alias_target(&current, class)? {
+            if !seen.insert(next.clone()) {
+                return Err(format!("alias loop at {}", next).into());
+            }
+            chain.push(next.clone());
+            current = next;
+        }
+        Ok(chain)
+    }
+
+    // the A and AAAA records of the final target of the alias chain
+    pub fn final_addresses(&self) -> Result<Vec<IpAddr>, Box<dyn std::error::Error>> {
+        let chain = self.resolve_chain()?;
+        let target = chain.last().ok_or("alias chain is empty")?;
+        let class = self.queries[0].get_class();
+        let mut addresses = vec![];
+        for answer in &self.answers {
+            if answer.name != *target || answer.get_class() != class {
+                continue;
+            }
+            match answer.get_rdata()? {
+                RData::A(ip) => addresses.push(IpAddr::V4(ip)),
+                RData::AAAA(ip) => addresses.push(IpAddr::V6(ip)),
+                _ => {}
+            }
+        }
+        Ok(addresses)
+    }
+
+    fn alias_target(
+        &self,
+        name: &Name,
+        class: RecordClass,
+    ) -> Result<Option<Name>, Box<dyn std::error::Error>> {
+        let in_class = |answer: &&Answer| answer.get_class() == class;
+        for answer in self.answers.iter().filter(in_class) {
+            if answer.name == *name && answer.get_record_type() == RecordType::CNAME {
+                if let RData::CNAME(target) = answer.get_rdata()? {
+                    return Ok(Some(target));
+                }
+            }
+        }
+        for answer in self.answers.iter().filter(in_class) {
+            // a DNAME redirects the subdomains of its owner, not the owner itself
+            if answer.get_record_type() != RecordType::DNAME
+                || !name.is_subdomain_of(&answer.name)
+                || name.num_labels() == answer.name.num_labels()
+            {
+                continue;
+            }
+            if let RData::DNAME(target) = answer.get_rdata()? {
+                let prefix = name.num_labels() - answer.name.num_labels();
+                let labels = name.labels()[..prefix]
+                    .iter()
+                    .chain(target.labels().iter())
+                    .cloned();
+                return Ok(Some(Name::from_labels(labels)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn record(name: &str, ttl: u32, rdata: RData) -> Answer {
+        Answer::from_record(name.parse().unwrap(), ttl, rdata).unwrap()
+    }
+
+    fn response(query: &str, ty: RecordType, answers: Vec<Answer>) -> DnsPacket {
+        let query = DnsPacket::builder().add_query(query, ty).unwrap().build();
+        answers
+            .into_iter()
+            .fold(DnsPacket::response_to(&query), |builder, answer| {
+                builder.add_answer(answer)
+            })
+            .build()
+    }
+
+    #[test]
+    fn test_rrsets() {
+        let packet = response(
+            "example.com",
+            RecordType::A,
+            vec![
+                record("example.com", 300, RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+                record("example.com", 300, RData::AAAA(Ipv6Addr::LOCALHOST)),
+                record("EXAMPLE.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 2))),
+            ],
+        );
+        let rrsets = packet.answer_rrsets();
+        assert_eq!(rrsets.len(), 2);
+        assert_eq!(rrsets[0].ty, RecordType::A);
+        assert_eq!(rrsets[0].ttl, 60);
+        assert_eq!(
+            rrsets[0].rdata().unwrap(),
+            vec![
+                RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                RData::A(Ipv4Addr::new(192, 0, 2, 2))
+            ]
+        );
+        assert_eq!(rrsets[1].records.len(), 1);
+    }
+
+    #[test]
+    fn test_alias_chain() {
+        let packet = response(
+            "www.example.com",
+            RecordType::A,
+            vec![
+                record(
+                    "www.example.com",
+                    300,
+                    RData::CNAME("www.example.net".parse().unwrap()),
+                ),
+                record(
+                    "example.net",
+                    300,
+                    RData::DNAME("example.org".parse().unwrap()),
+                ),
+                record("www.example.org", 60, RData::A(Ipv4Addr::new(192, 0, 2, 1))),
+                record("example.org", 60, RData::A(Ipv4Addr::new(192, 0, 2, 9))),
+            ],
+        );
+        let chain: Vec<String> = packet
+            .resolve_chain()
+            .unwrap()
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(
+            chain,
+            vec!["www.example.com.", "www.example.net.", "www.example.org."]
+        );
+        assert_eq!(
+            packet.final_addresses().unwrap(),
+            vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+        );
+
+        let packet = response(
+            "a.example.com",
+            RecordType::A,
+            vec![
+                record(
+                    "a.example.com",
+                    300,
+                    RData::CNAME("b.example.com".parse().unwrap()),
+                ),
+                record(
+                    "b.example.com",
+                    300,
+                    RData::CNAME("A.example.com".parse().unwrap()),
+                ),
+            ],
+        );
+        assert!(packet.resolve_chain().is_err());
+        assert!(packet.final_addresses().is_err());
+    }
+}
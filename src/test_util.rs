@@ -0,0 +1,45 @@
+// helpers for the tests that run a local TLS server
+
+use std::net::Ipv4Addr;
+
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+use crate::http::Client;
+use crate::{Answer, DnsPacket, RData};
+
+// a self-signed certificate for `name` and a server configuration that presents it
+pub(crate) fn self_signed_server(
+    name: &str,
+    alpn_protocols: &[&[u8]],
+) -> (rcgen::Certificate, ServerConfig) {
+    let certificate = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+    let mut server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(certificate.serialize_der().unwrap())],
+            PrivateKey(certificate.serialize_private_key_der()),
+        )
+        .unwrap();
+    server_config.alpn_protocols = alpn_protocols.iter().map(|alpn| alpn.to_vec()).collect();
+    (certificate, server_config)
+}
+
+// a client that trusts the self-signed certificate
+pub(crate) fn trusting_client(certificate: &rcgen::Certificate) -> Client {
+    Client::builder()
+        .add_root_certificates_pem(certificate.serialize_pem().unwrap().as_bytes())
+        .unwrap()
+        .build()
+}
+
+// answers the query with 192.0.2.`last_octet`
+pub(crate) fn a_response(query: &DnsPacket, last_octet: u8) -> DnsPacket {
+    let answer = Answer::from_record(
+        query.queries[0].name.clone(),
+        60,
+        RData::A(Ipv4Addr::new(192, 0, 2, last_octet)),
+    )
+    .unwrap();
+    DnsPacket::response_to(query).add_answer(answer).build()
+}
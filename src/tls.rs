@@ -0,0 +1,299 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use ring::digest::{digest, SHA256};
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, Error as TLSError, KeyLogFile, OwnedTrustAnchor, RootCertStore,
+    ServerName,
+};
+
+use crate::fingerprint::spki_from_certificate;
+use crate::http::Client;
+
+// SHA-256 hashes of the SubjectPublicKeyInfo a server has to present, by server name
+#[derive(Clone, Default)]
+pub(crate) struct SpkiPins {
+    pins: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl SpkiPins {
+    // `None` if the server has no pins and its certificate is checked against the roots. Only the
+    // end-entity key is compared, a pinned CA key could be sent along with any other certificate
+    pub(crate) fn check(&self, server_name: &str, end_entity: &[u8]) -> Option<Result<(), String>> {
+        let pins = self.pins.get(&server_name.to_ascii_lowercase())?;
+        let spki = match spki_from_certificate(end_entity) {
+            Ok(spki) => spki,
+            Err(error) => return Some(Err(error.to_string())),
+        };
+        let hash = digest(&SHA256, spki);
+        if pins.iter().any(|pin| pin.as_slice() == hash.as_ref()) {
+            Some(Ok(()))
+        } else {
+            Some(Err(format!(
+                "certificate of {} does not match a pinned key",
+                server_name
+            )))
+        }
+    }
+}
+
+// a pinned server is authenticated by its key alone, as in the strict profile of RFC 7858 4.2.
+// The handshake signature is still verified by rustls, so the server has to own the key
+struct PinningVerifier {
+    pins: SpkiPins,
+    roots: WebPkiVerifier,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, TLSError> {
+        let name = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => String::new(),
+        };
+        match self.pins.check(&name, &end_entity.0) {
+            Some(Ok(())) => Ok(ServerCertVerified::assertion()),
+            Some(Err(error)) => Err(TLSError::General(error)),
+            None => self.roots.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            ),
+        }
+    }
+}
+
+pub struct ClientBuilder {
+    webpki_roots: bool,
+    root_store: RootCertStore,
+    pins: SpkiPins,
+    addresses: HashMap<String, IpAddr>,
+    key_log: bool,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self {
+            webpki_roots: true,
+            root_store: RootCertStore::empty(),
+            pins: SpkiPins::default(),
+            addresses: HashMap::new(),
+            key_log: false,
+        }
+    }
+
+    // trusts the certificates of a PEM file in addition to the built in roots
+    pub fn add_root_certificates_pem(
+        mut self,
+        pem: &[u8],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let certificates = rustls_pemfile::certs(&mut &pem[..]).map_err(|_| "invalid PEM file")?;
+        let (valid, _) = self.root_store.add_parsable_certificates(&certificates);
+        if valid == 0 {
+            return Err("PEM file contains no usable certificate".into());
+        }
+        Ok(self)
+    }
+
+    // only the certificates added with `add_root_certificates_pem` are trusted
+    pub fn without_webpki_roots(mut self) -> Self {
+        self.webpki_roots = false;
+        self
+    }
+
+    // `pin` is the base64 encoded SHA-256 hash of the SubjectPublicKeyInfo, like pin-sha256 in
+    // RFC 7469. A server can have several pins, e.g. for a backup key
+    pub fn pin_spki_sha256(
+        mut self,
+        server_name: &str,
+        pin: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let hash = base64::decode(pin)?;
+        if hash.len() != 32 {
+            return Err(format!("pin {} is not a SHA-256 hash", pin).into());
+        }
+        self.pins
+            .pins
+            .entry(server_name.to_ascii_lowercase())
+            .or_default()
+            .push(hash);
+        Ok(self)
+    }
+
+    // connects to `addr` for `server_name`, SNI and the certificate check still use the name
+    pub fn resolve(mut self, server_name: &str, addr: IpAddr) -> Self {
+        self.addresses
+            .insert(server_name.to_ascii_lowercase(), addr);
+        self
+    }
+
+    // writes the session secrets to the file in SSLKEYLOGFILE, only meant for debugging
+    pub fn key_log(mut self) -> Self {
+        self.key_log = true;
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let mut roots = RootCertStore::empty();
+        if self.webpki_roots {
+            roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+        }
+        roots.roots.extend(self.root_store.roots);
+        let verifier = PinningVerifier {
+            pins: self.pins,
+            roots: WebPkiVerifier::new(roots, None),
+        };
+        let mut config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        if self.key_log {
+            config.key_log = Arc::new(KeyLogFile::new());
+        }
+        Client::with_tls_config(config, self.addresses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::net::Ipv4Addr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::runtime::Builder;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::http::DnsRequest;
+    use crate::test_util::{a_response, self_signed_server};
+    use crate::{DnsPacket, FromBytes, RData, RecordType};
+
+    use super::*;
+
+    #[test]
+    fn test_spki_pins() {
+        let certificate =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let der = certificate.serialize_der().unwrap();
+        let hash = digest(&SHA256, spki_from_certificate(&der).unwrap());
+        let pin = base64::encode(hash.as_ref());
+
+        let builder = ClientBuilder::new()
+            .pin_spki_sha256("LocalHost", &pin)
+            .unwrap();
+        assert_eq!(builder.pins.check("localhost", &der), Some(Ok(())));
+        assert_eq!(builder.pins.check("example.com", &der), None);
+        let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        assert!(matches!(
+            builder
+                .pins
+                .check("localhost", &other.serialize_der().unwrap()),
+            Some(Err(_))
+        ));
+
+        assert!(ClientBuilder::new()
+            .pin_spki_sha256("localhost", "c2hvcnQ=")
+            .is_err());
+        assert!(ClientBuilder::new()
+            .add_root_certificates_pem(b"not a certificate")
+            .is_err());
+        assert!(ClientBuilder::new()
+            .add_root_certificates_pem(certificate.serialize_pem().unwrap().as_bytes())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_pinned_dot() {
+        let (certificate, server_config) = self_signed_server("localhost", &[]);
+        let der = certificate.serialize_der().unwrap();
+        let pin = base64::encode(digest(&SHA256, spki_from_certificate(&der).unwrap()));
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    // the handshake fails for clients that reject the certificate
+                    let mut stream = match acceptor.accept(stream).await {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    let length = stream.read_u16().await.unwrap();
+                    let mut query = vec![0; length as usize];
+                    stream.read_exact(&mut query).await.unwrap();
+                    let query = DnsPacket::read(&mut Cursor::new(&query)).unwrap();
+                    let response = a_response(&query, 1).to_vec().unwrap();
+                    stream.write_u16(response.len() as u16).await.unwrap();
+                    stream.write_all(&response).await.unwrap();
+                    stream.flush().await.unwrap();
+                }
+            });
+
+            let request = || {
+                let query = DnsPacket::builder()
+                    .add_query("www.example.com", RecordType::A)
+                    .unwrap()
+                    .build();
+                // the certificate is for localhost, only the pin makes it acceptable
+                DnsRequest::new_with_host("dns.example", format!("dns.example:{}", port), query)
+            };
+            let pinned = ClientBuilder::new()
+                .without_webpki_roots()
+                .pin_spki_sha256("dns.example", &pin)
+                .unwrap()
+                .resolve("dns.example", Ipv4Addr::LOCALHOST.into())
+                .build();
+            let response = pinned.send_dot(request()).await.unwrap();
+            assert_eq!(
+                response.answers[0].get_rdata().unwrap(),
+                RData::A(Ipv4Addr::new(192, 0, 2, 1))
+            );
+
+            let wrong_pin = base64::encode([0u8; 32]);
+            let client = ClientBuilder::new()
+                .pin_spki_sha256("dns.example", &wrong_pin)
+                .unwrap()
+                .resolve("dns.example", Ipv4Addr::LOCALHOST.into())
+                .build();
+            assert!(client.send_dot(request()).await.is_err());
+            let unpinned = ClientBuilder::new()
+                .resolve("dns.example", Ipv4Addr::LOCALHOST.into())
+                .build();
+            assert!(unpinned.send_dot(request()).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::zone::{character_string_to_string, character_strings_from_str};
+
+// TXT rdata is a list of character-strings, which don't have to be valid UTF-8. The boundaries
+// are kept, since SPF and DKIM records depend on them. There is always at least one string, an
+// empty text is a single empty string like on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxtData {
+    strings: Vec<Vec<u8>>,
+}
+
+impl Default for TxtData {
+    fn default() -> Self {
+        TxtData {
+            strings: vec![vec![]],
+        }
+    }
+}
+
+impl TxtData {
+    pub fn new(strings: Vec<Vec<u8>>) -> Result<TxtData, Box<dyn std::error::Error>> {
+        if strings.iter().any(|string| string.len() > 255) {
+            return Err("character-string is longer than 255 bytes".into());
+        }
+        if strings.is_empty() {
+            return Ok(TxtData::default());
+        }
+        Ok(TxtData { strings })
+    }
+
+    // splits a longer text into as many 255 byte character-strings as needed
+    pub fn from_text(text: &str) -> TxtData {
+        if text.is_empty() {
+            return TxtData::default();
+        }
+        let strings = text.as_bytes().chunks(255).map(|c| c.to_vec()).collect();
+        TxtData { strings }
+    }
+
+    pub fn from_rdata(data: &[u8]) -> Result<TxtData, Box<dyn std::error::Error>> {
+        let mut strings = vec![];
+        let mut rest = data;
+        while let Some((length, remainder)) = rest.split_first() {
+            let length = *length as usize;
+            if remainder.len() < length {
+                return Err("character-string is longer than the TXT rdata".into());
+            }
+            strings.push(remainder[..length].to_vec());
+            rest = &remainder[length..];
+        }
+        TxtData::new(strings)
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut data = vec![];
+        for string in &self.strings {
+            data.push(string.len() as u8);
+            data.extend(string);
+        }
+        data
+    }
+
+    pub fn strings(&self) -> &[Vec<u8>] {
+        &self.strings
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.strings.concat()
+    }
+
+    // joins all character-strings, replacing invalid UTF-8
+    pub fn to_text_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.to_bytes()).to_string()
+    }
+}
+
+impl Display for TxtData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let strings: Vec<String> = self
+            .strings
+            .iter()
+            .map(|string| character_string_to_string(string))
+            .collect();
+        write!(f, "{}", strings.join(" "))
+    }
+}
+
+impl FromStr for TxtData {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TxtData::new(character_strings_from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_multiple_strings() {
+        let data = b"\x0bv=DKIM1; k=\x05rsa; \x03\xff\x00\"".to_vec();
+        let txt = TxtData::from_rdata(&data).unwrap();
+        assert_eq!(txt.strings().len(), 3);
+        assert_eq!(txt.strings()[2], vec![0xff, 0x00, b'"']);
+        assert_eq!(txt.to_rdata(), data);
+        assert_eq!(txt.to_string(), r#""v=DKIM1; k=" "rsa; " "\255\000\"""#);
+        assert_eq!(txt.to_string().parse::<TxtData>().unwrap(), txt);
+        assert_eq!(txt.to_text_lossy(), "v=DKIM1; k=rsa; \u{fffd}\u{0}\"");
+        assert!(TxtData::from_rdata(b"\x05abc").is_err());
+    }
+
+    #[test]
+    fn test_long_text() {
+        let text = "a".repeat(300);
+        let txt = TxtData::from_text(&text);
+        assert_eq!(txt.strings().len(), 2);
+        assert_eq!(txt.strings()[0].len(), 255);
+        assert_eq!(txt.to_text_lossy(), text);
+        assert_eq!(TxtData::from_text("").to_rdata(), vec![0]);
+        assert!(TxtData::new(vec![vec![0; 256]]).is_err());
+    }
+
+    #[test]
+    fn test_empty_text() {
+        let empty = TxtData::default();
+        assert_eq!(empty.to_rdata(), vec![0]);
+        assert_eq!(TxtData::from_rdata(&empty.to_rdata()).unwrap(), empty);
+        assert_eq!(TxtData::from_rdata(&[]).unwrap(), empty);
+        assert_eq!(TxtData::new(vec![]).unwrap(), empty);
+        assert_eq!(TxtData::from_text(""), empty);
+        assert_eq!(empty.to_string(), r#""""#);
+        assert_eq!(empty.to_string().parse::<TxtData>().unwrap(), empty);
+    }
+}
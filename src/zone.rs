@@ -0,0 +1,497 @@
+// Copyright (c) 2021 Patrick Amrein <amrein@ubique.ch>
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::fmt::Display;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::loc::Loc;
+use crate::name::Name;
+use crate::txt::TxtData;
+use crate::{Answer, ToBytes};
+
+const TYPES: [(&str, u16); 28] = [
+    ("A", 1),
+    ("NS", 2),
+    ("CNAME", 5),
+    ("SOA", 6),
+    ("PTR", 12),
+    ("HINFO", 13),
+    ("MX", 15),
+    ("TXT", 16),
+    ("RP", 17),
+    ("AAAA", 28),
+    ("LOC", 29),
+    ("SRV", 33),
+    ("NAPTR", 35),
+    ("DNAME", 39),
+    ("OPT", 41),
+    ("DS", 43),
+    ("SSHFP", 44),
+    ("RRSIG", 46),
+    ("NSEC", 47),
+    ("DNSKEY", 48),
+    ("TLSA", 52),
+    ("SVCB", 64),
+    ("HTTPS", 65),
+    ("IXFR", 251),
+    ("AXFR", 252),
+    ("ANY", 255),
+    ("URI", 256),
+    ("CAA", 257),
+];
+
+const CLASSES: [(&str, u16); 5] = [("IN", 1), ("CH", 3), ("HS", 4), ("NONE", 254), ("ANY", 255)];
+
+pub fn type_to_string(ty: u16) -> String {
+    match TYPES.iter().find(|(_, value)| *value == ty) {
+        Some((mnemonic, _)) => mnemonic.to_string(),
+        None => format!("TYPE{}", ty),
+    }
+}
+
+pub fn type_from_str(ty: &str) -> Result<u16, Box<dyn std::error::Error>> {
+    from_str(ty, "TYPE", &TYPES)
+}
+
+pub fn class_to_string(class: u16) -> String {
+    match CLASSES.iter().find(|(_, value)| *value == class) {
+        Some((mnemonic, _)) => mnemonic.to_string(),
+        None => format!("CLASS{}", class),
+    }
+}
+
+pub fn class_from_str(class: &str) -> Result<u16, Box<dyn std::error::Error>> {
+    from_str(class, "CLASS", &CLASSES)
+}
+
+fn from_str(
+    text: &str,
+    prefix: &str,
+    mnemonics: &[(&str, u16)],
+) -> Result<u16, Box<dyn std::error::Error>> {
+    let upper = text.to_ascii_uppercase();
+    if let Some((_, value)) = mnemonics.iter().find(|(mnemonic, _)| *mnemonic == upper) {
+        return Ok(*value);
+    }
+    match upper.strip_prefix(prefix) {
+        Some(number) if !number.is_empty() => Ok(number.parse()?),
+        _ => Err(format!("unknown {} {}", prefix.to_ascii_lowercase(), text).into()),
+    }
+}
+
+// RFC 3597 generic rdata representation: `\# <length> <hex>`
+pub fn generic_rdata_to_string(data: &[u8]) -> String {
+    if data.is_empty() {
+        "\\# 0".to_string()
+    } else {
+        format!("\\# {} {}", data.len(), hex::encode_upper(data))
+    }
+}
+
+pub fn generic_rdata_from_str(rdata: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut parts = rdata.split_ascii_whitespace();
+    if parts.next() != Some("\\#") {
+        return Err("generic rdata has to start with \\#".into());
+    }
+    let length: usize = parts
+        .next()
+        .ok_or("generic rdata is missing the length")?
+        .parse()?;
+    let data = hex::decode(parts.collect::<String>())?;
+    if data.len() != length {
+        return Err(format!(
+            "generic rdata length {} does not match {} bytes of data",
+            length,
+            data.len()
+        )
+        .into());
+    }
+    Ok(data)
+}
+
+pub(crate) fn character_strings_from_str(
+    text: &str,
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+    let mut strings = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        strings.push(next_character_string(&mut rest)?);
+    }
+    Ok(strings)
+}
+
+// a quoted or unquoted string from the start of `text` and the text after it, without the length
+// limit of a character-string
+fn split_string(text: &str) -> Result<(Vec<u8>, &str), Box<dyn std::error::Error>> {
+    let quoted = text.starts_with('"');
+    let mut chars = text.char_indices().skip(quoted as usize);
+    let mut string = vec![];
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' if quoted => return Ok((string, &text[index + 1..])),
+            c if !quoted && c.is_ascii_whitespace() => return Ok((string, &text[index..])),
+            '\\' => {
+                let (_, escaped) = chars.next().ok_or("dangling escape in character-string")?;
+                if escaped.is_ascii_digit() {
+                    // \DDD takes exactly three decimal digits
+                    let digits: String = std::iter::once(escaped)
+                        .chain(chars.by_ref().take(2).map(|(_, c)| c))
+                        .collect();
+                    let invalid = || format!("invalid escape sequence \\{}", digits);
+                    if digits.len() != 3 || !digits.chars().all(|c| c.is_ascii_digit()) {
+                        return Err(invalid().into());
+                    }
+                    string.push(digits.parse::<u8>().map_err(|_| invalid())?);
+                } else {
+                    let mut buf = [0; 4];
+                    string.extend(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+            c => {
+                let mut buf = [0; 4];
+                string.extend(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    if quoted {
+        return Err("unterminated character-string".into());
+    }
+    Ok((string, ""))
+}
+
+fn next_character_string(rest: &mut &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if rest.is_empty() {
+        return Err("rdata is missing a field".into());
+    }
+    let (string, remainder) = split_string(rest)?;
+    if string.len() > 255 {
+        return Err("character-string is longer than 255 bytes".into());
+    }
+    *rest = remainder.trim_start();
+    Ok(string)
+}
+
+fn push_character_string(data: &mut Vec<u8>, string: Vec<u8>) {
+    data.push(string.len() as u8);
+    data.extend(string);
+}
+
+// a field as it is written, e.g. a number or a name with its escapes
+fn next_token<'a>(rest: &mut &'a str) -> Result<&'a str, Box<dyn std::error::Error>> {
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let (token, remainder) = rest.split_at(end);
+    if token.is_empty() {
+        return Err("rdata is missing a field".into());
+    }
+    *rest = remainder.trim_start();
+    Ok(token)
+}
+
+pub(crate) fn character_string_to_string(data: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for byte in data {
+        match byte {
+            b'"' | b'\\' => {
+                text.push('\\');
+                text.push(*byte as char);
+            }
+            0x20..=0x7e => text.push(*byte as char),
+            _ => text.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    text.push('"');
+    text
+}
+
+pub(crate) fn rdata_from_str(ty: u16, rdata: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if rdata.starts_with("\\#") {
+        return generic_rdata_from_str(rdata);
+    }
+    let mut data = vec![];
+    match ty {
+        1 => data.extend(rdata.parse::<Ipv4Addr>()?.octets()),
+        28 => data.extend(rdata.parse::<Ipv6Addr>()?.octets()),
+        2 | 5 | 12 | 39 => rdata.parse::<Name>()?.write(&mut data)?,
+        6 => {
+            let mut rest = rdata.trim();
+            for _ in 0..2 {
+                next_token(&mut rest)?.parse::<Name>()?.write(&mut data)?;
+            }
+            for _ in 0..5 {
+                data.extend(next_token(&mut rest)?.parse::<u32>()?.to_be_bytes());
+            }
+            if !rest.is_empty() {
+                return Err(format!("trailing data in SOA rdata: {}", rest).into());
+            }
+        }
+        15 => {
+            let (preference, exchange) = rdata
+                .split_once(char::is_whitespace)
+                .ok_or("MX rdata must contain preference and exchange")?;
+            data.extend(preference.parse::<u16>()?.to_be_bytes());
+            exchange.trim().parse::<Name>()?.write(&mut data)?;
+        }
+        16 => data = rdata.parse::<TxtData>()?.to_rdata(),
+        // SRV has no record data type of its own, but is common enough to be read
+        33 => {
+            let mut rest = rdata.trim();
+            for _ in 0..3 {
+                data.extend(next_token(&mut rest)?.parse::<u16>()?.to_be_bytes());
+            }
+            next_token(&mut rest)?.parse::<Name>()?.write(&mut data)?;
+            if !rest.is_empty() {
+                return Err(format!("trailing data in SRV rdata: {}", rest).into());
+            }
+        }
+        29 => data = rdata.parse::<Loc>()?.to_rdata(),
+        13 | 17 | 35 | 256 | 257 => data = fields_rdata_from_str(ty, rdata)?,
+        // SSHFP and TLSA: a few numeric fields followed by hex data, which may contain spaces
+        44 | 52 => {
+            let fields = if ty == 44 { 2 } else { 3 };
+            let mut parts = rdata.split_ascii_whitespace();
+            for _ in 0..fields {
+                data.push(
+                    parts
+                        .next()
+                        .ok_or("rdata is missing a field")?
+                        .parse::<u8>()?,
+                );
+            }
+            data.extend(hex::decode(parts.collect::<String>())?);
+        }
+        _ => {
+            return Err(format!(
+                "{} rdata has to be given in the generic \\# form",
+                type_to_string(ty)
+            )
+            .into())
+        }
+    }
+    Ok(data)
+}
+
+// HINFO, RP, NAPTR, URI and CAA, which mix numbers, character-strings and names
+fn fields_rdata_from_str(ty: u16, rdata: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut rest = rdata.trim();
+    let mut data = vec![];
+    match ty {
+        13 => {
+            for _ in 0..2 {
+                push_character_string(&mut data, next_character_string(&mut rest)?);
+            }
+        }
+        17 => {
+            for _ in 0..2 {
+                next_token(&mut rest)?.parse::<Name>()?.write(&mut data)?;
+            }
+        }
+        35 => {
+            for _ in 0..2 {
+                data.extend(next_token(&mut rest)?.parse::<u16>()?.to_be_bytes());
+            }
+            for _ in 0..3 {
+                push_character_string(&mut data, next_character_string(&mut rest)?);
+            }
+            next_token(&mut rest)?.parse::<Name>()?.write(&mut data)?;
+        }
+        // the URI target and the CAA value fill the rest of the rdata, so they can be longer than
+        // a character-string
+        256 => {
+            for _ in 0..2 {
+                data.extend(next_token(&mut rest)?.parse::<u16>()?.to_be_bytes());
+            }
+            let (target, remainder) = split_string(rest)?;
+            data.extend(target);
+            rest = remainder.trim_start();
+        }
+        257 => {
+            data.push(next_token(&mut rest)?.parse::<u8>()?);
+            // the tag is 1 to 255 letters and digits (RFC 8659 4.1)
+            let tag = next_token(&mut rest)?;
+            if tag.len() > 255 || !tag.bytes().all(|byte| byte.is_ascii_alphanumeric()) {
+                return Err(format!("invalid CAA tag {}", tag).into());
+            }
+            push_character_string(&mut data, tag.as_bytes().to_vec());
+            let (value, remainder) = split_string(rest)?;
+            data.extend(value);
+            rest = remainder.trim_start();
+        }
+        _ => return Err(format!("{} rdata is not made of fields", type_to_string(ty)).into()),
+    }
+    if !rest.is_empty() {
+        return Err(format!("trailing data in {} rdata: {}", type_to_string(ty), rest).into());
+    }
+    Ok(data)
+}
+
+impl Answer {
+    // parses a single resource record line of a zone file, `<owner> [<ttl>] [<class>] <type> <rdata>`
+    pub fn from_zone_line(line: &str) -> Result<Answer, Box<dyn std::error::Error>> {
+        let mut rest = line.trim();
+        let mut next_token = || {
+            let token_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (token, remainder) = rest.split_at(token_end);
+            rest = remainder.trim_start();
+            token
+        };
+        let owner = next_token();
+        let mut ttl = 0;
+        let mut class = 1;
+        let ty = loop {
+            let token = next_token();
+            if token.is_empty() {
+                return Err("resource record is missing its type".into());
+            } else if let Ok(value) = token.parse::<u32>() {
+                ttl = value;
+            } else if let Ok(value) = class_from_str(token) {
+                class = value;
+            } else {
+                break type_from_str(token)?;
+            }
+        };
+        let data = rdata_from_str(ty, rest)?;
+        Answer::from_rdata(owner.parse()?, ty, class, ttl, data)
+    }
+
+    pub fn rdata_to_string(&self) -> String {
+        match self.get_rdata() {
+            Ok(rdata) => rdata.to_string(),
+            Err(_) => generic_rdata_to_string(&self.data),
+        }
+    }
+}
+
+impl Display for Answer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.name,
+            self.ttl,
+            class_to_string(self.class),
+            type_to_string(self.ty),
+            self.rdata_to_string()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Answer, RData};
+
+    use super::*;
+
+    #[test]
+    fn test_generic_round_trip() {
+        let answer =
+            Answer::from_zone_line("example.com. 3600 CLASS32 TYPE65534 \\# 4 0A 00 00 01")
+                .unwrap();
+        assert_eq!(answer.class, 32);
+        assert_eq!(answer.ttl, 3600);
+        assert!(matches!(
+            answer.get_rdata(),
+            Ok(RData::Unknown(65534, data)) if data == vec![10, 0, 0, 1]
+        ));
+        let line = answer.to_string();
+        assert_eq!(
+            line,
+            "example.com.\t3600\tCLASS32\tTYPE65534\t\\# 4 0A000001"
+        );
+        assert_eq!(Answer::from_zone_line(&line).unwrap().to_string(), line);
+
+        let soa = "example.com.\t60\tIN\tSOA\tns.example.com. host.example.com. 1 2 3 4 5";
+        assert_eq!(Answer::from_zone_line(soa).unwrap().to_string(), soa);
+        assert!(Answer::from_zone_line("example.com. SOA ns.example.com. host. 1 2 3 4").is_err());
+    }
+
+    #[test]
+    fn test_known_type_in_generic_form() {
+        let answer = Answer::from_zone_line("a.example. IN A \\# 4 C0000201").unwrap();
+        assert!(matches!(
+            answer.get_rdata(),
+            Ok(RData::A(ip)) if ip == Ipv4Addr::new(192, 0, 2, 1)
+        ));
+        assert_eq!(answer.to_string(), "a.example.\t0\tIN\tA\t192.0.2.1");
+        assert!(Answer::from_zone_line("a.example. IN A \\# 5 C0000201").is_err());
+        assert!(Answer::from_zone_line("a.example. IN TYPE999 10.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_txt_strings() {
+        let answer =
+            Answer::from_zone_line(r#"t.example. 60 TXT "v=spf1 " include:x.example \255"#)
+                .unwrap();
+        assert_eq!(
+            answer.to_string(),
+            "t.example.\t60\tIN\tTXT\t\"v=spf1 \" \"include:x.example\" \"\\255\""
+        );
+        assert!(matches!(
+            answer.get_rdata(),
+            Ok(RData::TXT(txt)) if txt.strings().len() == 3 && txt.strings()[2] == [255]
+        ));
+        for escape in &["\\1", "\\25x", "\\256"] {
+            assert!(Answer::from_zone_line(&format!("t.example. TXT {}", escape)).is_err());
+        }
+        assert!(matches!(
+            Answer::from_zone_line("t.example. TXT \\0010").unwrap().get_rdata(),
+            Ok(RData::TXT(txt)) if txt.strings()[0] == [1, b'0']
+        ));
+    }
+
+    #[test]
+    fn test_rp_and_uri_fields() {
+        let answer =
+            Answer::from_zone_line(r"example.com. RP john\.doe.example.com. txt\\.example.com.")
+                .unwrap();
+        assert!(matches!(
+            answer.get_rdata(),
+            Ok(RData::RP { mbox, txt })
+                if mbox.labels().len() == 3 && mbox.labels()[0] == b"john.doe"
+                    && txt.labels()[0] == b"txt\\"
+        ));
+        assert_eq!(
+            answer.to_string(),
+            "example.com.\t0\tIN\tRP\tjohn\\.doe.example.com. txt\\\\.example.com."
+        );
+
+        let target = format!("https://example.com/{}", "a".repeat(300));
+        let answer =
+            Answer::from_zone_line(&format!("_http._tcp.example.com. URI 10 1 \"{}\"", target))
+                .unwrap();
+        assert!(matches!(
+            answer.get_rdata(),
+            Ok(RData::URI { target: data, .. }) if data == target.as_bytes()
+        ));
+        assert_eq!(
+            Answer::from_zone_line(&answer.to_string())
+                .unwrap()
+                .to_string(),
+            answer.to_string()
+        );
+        let value = "v".repeat(300);
+        let answer =
+            Answer::from_zone_line(&format!("example.com. CAA 0 issue \"{}\"", value)).unwrap();
+        assert!(matches!(
+            answer.get_rdata(),
+            Ok(RData::CAA { value: data, .. }) if data == value.as_bytes()
+        ));
+        let long_tag = format!("example.com. CAA 0 {} \"ca.example\"", "t".repeat(300));
+        assert!(Answer::from_zone_line(&long_tag).is_err());
+        assert!(Answer::from_zone_line("example.com. CAA 0 is-sue \"ca.example\"").is_err());
+        assert!(Answer::from_zone_line("example.com. HINFO \"one\"").is_err());
+        assert!(Answer::from_zone_line("example.com. RP a.example. b.example. c").is_err());
+    }
+
+    #[test]
+    fn test_type_mnemonics() {
+        assert_eq!(type_from_str("aaaa").unwrap(), 28);
+        assert_eq!(type_from_str("TYPE65534").unwrap(), 65534);
+        assert_eq!(type_to_string(65534), "TYPE65534");
+        assert_eq!(class_from_str("CLASS255").unwrap(), 255);
+        assert_eq!(class_to_string(3), "CH");
+        assert!(type_from_str("TYPE").is_err());
+        assert!(type_from_str("BOGUS").is_err());
+    }
+}